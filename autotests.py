@@ -8,11 +8,11 @@ from time import sleep
 base_bin = sys.argv[1]
 timeout = "22"
 print("Running nodes...")
-main_node = subprocess.Popen(["python3", "test_node_runner.py", timeout, base_bin, "--cert=tls/cert.pem", "--key=tls/key.pem", "--period=5", "--port=8080", "--bind=127.0.0.1"], stdout=subprocess.PIPE, stderr=subprocess.STDOUT)
+main_node = subprocess.Popen(["python3", "test_node_runner.py", timeout, base_bin, "--cert=tls/peer.pem", "--key=tls/peer.key", "--ca=tls/ca.pem", "--period=5", "--port=8080", "--bind=127.0.0.1"], stdout=subprocess.PIPE, stderr=subprocess.STDOUT)
 sleep(0.01)
-second_node = subprocess.Popen(["python3", "test_node_runner.py", timeout, base_bin, "--cert=tls/cert.pem", "--key=tls/key.pem", "--period=7", "--port=8081", "--bind=127.0.0.1", "--connect=127.0.0.1:8080"], stdout=subprocess.PIPE, stderr=subprocess.STDOUT)
+second_node = subprocess.Popen(["python3", "test_node_runner.py", timeout, base_bin, "--cert=tls/peer.pem", "--key=tls/peer.key", "--ca=tls/ca.pem", "--period=7", "--port=8081", "--bind=127.0.0.1", "--connect=127.0.0.1:8080"], stdout=subprocess.PIPE, stderr=subprocess.STDOUT)
 sleep(0.01)
-third_node = subprocess.Popen(["python3", "test_node_runner.py", timeout, base_bin, "--cert=tls/cert.pem", "--key=tls/key.pem", "--period=9", "--port=8082", "--bind=127.0.0.1", "--connect=127.0.0.1:8081"], stdout=subprocess.PIPE, stderr=subprocess.STDOUT)
+third_node = subprocess.Popen(["python3", "test_node_runner.py", timeout, base_bin, "--cert=tls/peer.pem", "--key=tls/peer.key", "--ca=tls/ca.pem", "--period=9", "--port=8082", "--bind=127.0.0.1", "--connect=127.0.0.1:8081"], stdout=subprocess.PIPE, stderr=subprocess.STDOUT)
 
 main_out, second_out, third_out = main_node.communicate()[0].decode("utf8"), second_node.communicate()[0].decode("utf8"), third_node.communicate()[0].decode("utf8")
 
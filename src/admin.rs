@@ -0,0 +1,65 @@
+use crate::node::Node;
+use crate::p2pcache::PeerQuery;
+use crate::saabisu::SaabisuConfig;
+
+use warp::{http::StatusCode, Filter, Rejection, Reply};
+use log::{error, info};
+use serde::Serialize;
+
+use std::net::SocketAddr;
+
+/// Description of the running node.
+#[derive(Serialize, Debug, Clone)]
+pub struct NodeInfo {
+    pub id: String,
+    /// Version of the crate
+    pub version: String,
+    /// Seconds since the node has been created
+    pub uptime: u64,
+    /// Advertised address of the node
+    pub address: Option<String>,
+    pub config: SaabisuConfig,
+}
+
+// Replies with JSON of the value or INTERNAL_SERVER_ERROR if it couldn't be got.
+fn reply_json<T: Serialize>(value: Result<T, String>) -> warp::reply::Response {
+    match value {
+        Ok(val) => warp::reply::json(&val).into_response(),
+        Err(err) => {
+            error!("Error on serving admin request: {}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Routes of the read-only admin API of the `node`:
+///  * `/info` - id, version, uptime, address and options of the node
+///  * `/peers` - all known peers with their states, accepts the same query as the peer-to-peer `/peers`
+///  * `/stats` - counters of the gossip activity
+pub fn admin_routes(node: &Node) -> impl Filter<Extract=(impl Reply,), Error=Rejection> + Clone {
+    let node_info = node.clone();
+    let node_peers = node.clone();
+    let node_stats = node.clone();
+
+    let info_srv = warp::path("info")
+        .and(warp::path::end())
+        .map(move || reply_json(node_info.info()));
+
+    let peers_srv = warp::path("peers")
+        .and(warp::path::end())
+        .and(warp::query::<PeerQuery>())
+        .map(move |query: PeerQuery| reply_json(node_peers.cache().query(&query)));
+
+    let stats_srv = warp::path("stats")
+        .and(warp::path::end())
+        .map(move || warp::reply::json(&node_stats.stats()));
+
+    warp::get().and(info_srv.or(peers_srv).or(stats_srv))
+}
+
+/// Serves read-only admin API of the `node` on `addr` over plain HTTP, separately from
+/// the peer-to-peer API, so it should be bound to the loopback or another private interface.
+pub async fn run_admin(addr: SocketAddr, node: &Node) {
+    info!("admin API on http://{}", addr);
+    warp::serve(admin_routes(node)).run(addr).await;
+}
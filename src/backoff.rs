@@ -0,0 +1,28 @@
+use std::time::Duration;
+
+/// Exponential backoff between failed attempts: every delay is twice as long as the previous one,
+/// starting with `min` and capped at `max`.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    current: Duration
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Self {
+        Backoff { min, max, current: min }
+    }
+
+    /// Returns the delay before the next attempt and doubles it.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.current;
+        self.current = (self.current * 2).min(self.max);
+        delay
+    }
+
+    /// Starts over from the `min` delay after a successful attempt.
+    pub fn reset(&mut self) {
+        self.current = self.min;
+    }
+}
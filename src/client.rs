@@ -0,0 +1,81 @@
+use log::error;
+
+use std::fs;
+use std::time;
+
+/// Timeout of every request to the peer.
+pub const REQUEST_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+
+/// Timeout of the direct and indirect probes, shorter than the timeout of the `/ping-req` request itself.
+pub const PROBE_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
+/// How certificates of the peers are verified.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, Default)]
+pub enum Verification {
+    /// Against the cluster CA certificate from the PEM file. System roots aren't trusted,
+    /// so only peers with certificates issued by the cluster CA are accepted.
+    Ca(String),
+    /// Against the system root certificates.
+    #[default]
+    System,
+    /// Certificates aren't verified at all, so anyone can impersonate a peer.
+    Insecure,
+}
+
+/// TLS options of the node, used both by the client and the server.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct TlsConfig {
+    /// Path to the certificate of the node
+    pub cert: String,
+    /// Path to the private key of the node, which must be in PKCS#8 format for the cluster CA
+    pub key: String,
+    /// How certificates of other peers are verified. With the cluster CA peers also
+    /// authenticate each other with their certificates
+    pub verification: Verification,
+}
+
+impl TlsConfig {
+    /// Returns true if peers must present certificates issued by the cluster CA.
+    pub fn is_mutual(&self) -> bool {
+        matches!(self.verification, Verification::Ca(_))
+    }
+}
+
+// Builds TLS connector, which verifies peers according to `verification`
+// and presents the certificate of the node if it's mutual.
+fn build_tls(tls: &TlsConfig) -> Result<native_tls::TlsConnector, String> {
+    let mut builder = native_tls::TlsConnector::builder();
+    match &tls.verification {
+        Verification::Ca(path) => {
+            let pem = fs::read(path).map_err(|err| format!("Error on reading the CA `{}`: {:?}", path, err))?;
+            let ca = native_tls::Certificate::from_pem(&pem)
+                .map_err(|err| format!("Error on parsing the CA `{}`: {:?}", path, err))?;
+            let cert = fs::read(&tls.cert).map_err(|err| format!("Error on reading the certificate `{}`: {:?}", tls.cert, err))?;
+            let key = fs::read(&tls.key).map_err(|err| format!("Error on reading the key `{}`: {:?}", tls.key, err))?;
+            let identity = native_tls::Identity::from_pkcs8(&cert, &key)
+                .map_err(|err| format!("Error on loading the certificate `{}`: {:?}", tls.cert, err))?;
+            builder.add_root_certificate(ca).disable_built_in_roots(true).identity(identity);
+        },
+        Verification::System => {},
+        Verification::Insecure => {
+            builder.use_sni(false).danger_accept_invalid_certs(true);
+        }
+    };
+    builder.build().map_err(|err| format!("Error on building the TLS connector: {:?}", err))
+}
+
+/// Builds HTTPS client, which is shared by all services and the server of the node.
+/// Connections to peers are pooled and kept alive, so the TLS handshake is done only
+/// once per peer instead of once per request.
+pub fn build_client(tls: &TlsConfig) -> Result<reqwest::Client, String> {
+    reqwest::ClientBuilder::new()
+        .use_preconfigured_tls(build_tls(tls)?)
+        .timeout(REQUEST_TIMEOUT)
+        .pool_idle_timeout(time::Duration::from_secs(90))
+        .tcp_keepalive(time::Duration::from_secs(60))
+        .build()
+        .map_err(|err| {
+            error!("Error on building the client: {:?}", err);
+            format!("Error on building the client: {:?}", err)
+        })
+}
@@ -0,0 +1,67 @@
+use chrono::prelude::*;
+
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+/// Source of the physical time in milliseconds, used by PeerCache.
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> i64;
+}
+
+/// Current UTC time.
+#[derive(Debug, Clone, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> i64 {
+        Utc::now().timestamp_millis()
+    }
+}
+
+/// Clock, that is moved only manually. All clones share the same time,
+/// so it can be changed after the clock has been passed to PeerCache.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    time: Arc<AtomicI64>
+}
+
+impl ManualClock {
+    pub fn new(time: i64) -> Self {
+        ManualClock { time: Arc::new(AtomicI64::new(time)) }
+    }
+
+    pub fn set(&self, time: i64) {
+        self.time.store(time, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, delta: i64) {
+        self.time.fetch_add(delta, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> i64 {
+        self.time.load(Ordering::SeqCst)
+    }
+}
+
+/// Clock, that is shifted from the inner one by `offset` milliseconds.
+/// Useful for simulating drifting nodes.
+#[derive(Debug, Clone)]
+pub struct OffsetClock {
+    inner: Arc<dyn Clock>,
+    offset: i64
+}
+
+impl OffsetClock {
+    pub fn new(inner: Arc<dyn Clock>, offset: i64) -> Self {
+        OffsetClock { inner, offset }
+    }
+}
+
+impl Clock for OffsetClock {
+    fn now(&self) -> i64 {
+        self.inner.now() + self.offset
+    }
+}
@@ -0,0 +1,56 @@
+use std::fmt;
+
+/// Hybrid logical clock timestamp - physical time in milliseconds with logical counter,
+/// which orders events happened within the same millisecond or while physical clock
+/// is behind the clock of other peers.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Hlc {
+    pub wall: i64,
+    pub logical: u32
+}
+
+impl Hlc {
+    pub fn new(wall: i64, logical: u32) -> Self {
+        Hlc { wall, logical }
+    }
+
+    /// Advances the clock for the local event happened at `physical` time and returns new timestamp.
+    pub fn tick(&mut self, physical: i64) -> Hlc {
+        if physical > self.wall {
+            self.wall = physical;
+            self.logical = 0;
+        } else {
+            self.logical = self.logical.saturating_add(1);
+        }
+        *self
+    }
+
+    /// Advances the clock on receiving the `remote` timestamp at `physical` time and returns new timestamp.
+    /// Logical counter saturates instead of overflowing, so the clock never goes backwards.
+    pub fn observe(&mut self, remote: &Hlc, physical: i64) -> Hlc {
+        if physical > self.wall && physical > remote.wall {
+            self.wall = physical;
+            self.logical = 0;
+        } else if remote.wall > self.wall {
+            self.wall = remote.wall;
+            self.logical = remote.logical.saturating_add(1);
+        } else if remote.wall == self.wall {
+            self.logical = self.logical.max(remote.logical).saturating_add(1);
+        } else {
+            self.logical = self.logical.saturating_add(1);
+        }
+        *self
+    }
+}
+
+impl From<i64> for Hlc {
+    fn from(wall: i64) -> Self {
+        Hlc::new(wall, 0)
+    }
+}
+
+impl fmt::Display for Hlc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.wall, self.logical)
+    }
+}
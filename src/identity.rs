@@ -0,0 +1,48 @@
+use x509_parser::prelude::{FromDer, GeneralName, X509Certificate};
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// Identity of the peer taken from its client certificate: DNS names and IPs from
+/// `subjectAltName` or, if there are none, the common name of the subject.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerIdentity {
+    pub names: Vec<String>
+}
+
+// Normalizes IP addresses, so `::1` and `0:0:0:0:0:0:0:1` are equal.
+fn normalize(host: &str) -> String {
+    host.parse::<IpAddr>().map(|ip| ip.to_string()).unwrap_or_else(|_| host.to_lowercase())
+}
+
+impl PeerIdentity {
+    /// Parses DER encoded certificate.
+    pub fn from_der(der: &[u8]) -> Result<Self, String> {
+        let (_, cert) = X509Certificate::from_der(der)
+            .map_err(|err| format!("Error on parsing the certificate: {:?}", err))?;
+        let mut names = vec![];
+        if let Ok(Some(san)) = cert.subject_alternative_name() {
+            for name in &san.value.general_names {
+                match name {
+                    GeneralName::DNSName(dns) => names.push(normalize(dns)),
+                    GeneralName::IPAddress(ip) => match ip.len() {
+                        4 => names.push(Ipv4Addr::from(<[u8; 4]>::try_from(*ip).unwrap_or_default()).to_string()),
+                        16 => names.push(Ipv6Addr::from(<[u8; 16]>::try_from(*ip).unwrap_or_default()).to_string()),
+                        _ => {}
+                    },
+                    _ => {}
+                }
+            }
+        }
+        if names.is_empty() {
+            names.extend(cert.subject().iter_common_name().filter_map(|cn| cn.as_str().ok()).map(normalize));
+        }
+        Ok(PeerIdentity { names })
+    }
+
+    /// Returns true if the host of the `address:port` is one of the names of the identity.
+    pub fn matches(&self, address: &str) -> bool {
+        let host = address.rsplit_once(':').map_or(address, |(host, _)| host);
+        let host = normalize(host.trim_start_matches('[').trim_end_matches(']'));
+        self.names.contains(&host)
+    }
+}
@@ -1,7 +1,19 @@
 #[macro_use]
 extern crate serde_derive;
 
+pub mod admin;
+pub mod backoff;
+pub mod client;
+pub mod clock;
+pub mod hlc;
+pub mod identity;
+pub mod merkle;
+pub mod node;
 pub mod p2pcache;
+pub mod resolver;
 pub mod server;
+pub mod signing;
+pub mod stats;
 pub mod saabisu;
+pub mod selector;
 pub mod waiter;
\ No newline at end of file
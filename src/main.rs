@@ -1,10 +1,22 @@
 extern crate simplep2pgossip;
-use simplep2pgossip::server::run_server;
+use simplep2pgossip::client::{TlsConfig, Verification};
+use simplep2pgossip::node::{load_or_generate_id, Node};
 use simplep2pgossip::p2pcache::PeerCache;
+use simplep2pgossip::resolver::SystemResolver;
+use simplep2pgossip::signing::NodeKey;
 
 use clap::{Parser};
+use log::{error, info, warn};
 use env_logger::Env;
-use simplep2pgossip::saabisu::run_saabisu;
+use rand::{distributions::Alphanumeric, Rng};
+use simplep2pgossip::saabisu::SaabisuConfig;
+use simplep2pgossip::server::ServerConfig;
+use tokio::signal::unix::{signal, SignalKind};
+
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::thread;
+use std::time;
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
@@ -15,30 +27,212 @@ struct Args {
     /// Timeout for peer connection
     #[clap(long, default_value_t=30)]
     timeout: u32,
+    /// Seconds removed peers are remembered, so stale gossip doesn't bring them back. Defaults to twice the timeout
+    #[clap(long)]
+    tombstone_ttl: Option<u32>,
     /// IP to bind the server one
     #[clap(long, default_value="127.0.0.1")]
     bind: String,
     /// Port to bind on
     #[clap(long, default_value_t = 8080)]
     port: u16,
+    /// address:port, which other peers connect to. Defaults to the bound IP and port
+    #[clap(long)]
+    advertise: Option<String>,
+    /// Port of the read-only admin API over plain HTTP. If absent, the API is disabled
+    #[clap(long)]
+    admin_port: Option<u16>,
+    /// IP to bind the admin API on
+    #[clap(long, default_value="127.0.0.1")]
+    admin_bind: String,
     /// Path to the TLS certificate
     #[clap(long, default_value="cert.pem")]
     cert: String,
     /// Path to the TLS private key
     #[clap(long, default_value="key.rsa")]
     key: String,
-    /// address:port to make first connection. If absent, server will just listen to bound port
+    /// Path to the cluster CA certificate. Peers present their certificates to each other and verify them against it
+    #[clap(long, conflicts_with="insecure")]
+    ca: Option<String>,
+    /// Don't verify certificates of other peers. Anyone will be able to impersonate a peer
+    #[clap(long)]
+    insecure: bool,
+    /// Number of peers asked to probe unreachable peer on our behalf
+    #[clap(long, default_value_t=3)]
+    indirect_probes: u32,
+    /// Seconds after which suspected peer is considered unavailable
+    #[clap(long, default_value_t=10)]
+    suspect_timeout: u32,
+    /// Maximal allowed clock difference with other peers in seconds
+    #[clap(long, default_value_t=60)]
+    max_drift: u32,
+    /// Period of anti-entropy rounds in seconds, 0 disables them
+    #[clap(long, default_value_t=10)]
+    sync_period: u32,
+    /// Path to the file, where known peers are saved and loaded from on start
+    #[clap(long)]
+    state_file: Option<String>,
+    /// Path to the ed25519 key of the node, which is generated if the file doesn't exist.
+    /// Without it new key is generated on every start
+    #[clap(long)]
+    node_key: Option<String>,
+    /// Path to the file with the id of the node, which is generated if the file doesn't exist.
+    /// Without it new id is generated on every start and the node is seen as a new peer.
+    /// Requires --node-key, since peers reject the known id signed with a new key
+    #[clap(long, requires="node-key")]
+    id_file: Option<String>,
+    /// Number of peers every message is sent or forwarded to
+    #[clap(long, default_value_t=3)]
+    fanout: usize,
+    /// Number of hops every message can be forwarded
+    #[clap(long, default_value_t=4)]
+    ttl: u32,
+    /// Number of peers pinged and sent updates to every round
+    #[clap(long, default_value_t=3)]
+    gossip_fanout: usize,
+    /// Prefer peers, which haven't been contacted for the longest time, over random ones
     #[clap(long)]
-    connect: Option<String>
+    prefer_stale: bool,
+    /// Metadata of the node as key=value, can be passed several times
+    #[clap(long="tag", parse(try_from_str=parse_tag), multiple_occurrences=true)]
+    tags: Vec<(String, String)>,
+    /// address:port of the seed peers to make first connection, separated by commas or passed several times.
+    /// DNS name gives all its A/AAAA records as seeds. If absent, server will just listen to bound port
+    #[clap(long, multiple_occurrences=true, use_value_delimiter=true)]
+    connect: Vec<String>,
+    /// Period of resolving the seeds again in seconds, so peers added to the DNS records are discovered. 0 disables it
+    #[clap(long, default_value_t=60)]
+    resolve_period: u32
+}
+
+// Parses `key=value` metadata tag.
+fn parse_tag(tag: &str) -> Result<(String, String), String> {
+    tag.split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| format!("Tag `{}` isn't in key=value format", tag))
+}
+
+fn random_msg() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(16)
+        .map(char::from)
+        .collect()
+}
+
+// Broadcasts random message every period and logs received ones.
+fn run_demo(node: &Node, period: u32) {
+    let node_copy = node.clone();
+    let node_copy_recv = node.clone();
+    let messages = node.subscribe().unwrap();
+    thread::spawn(move || {
+        for message in messages {
+            let origin = node_copy_recv.cache().address_of(&message.origin).ok().flatten()
+                .unwrap_or_else(|| message.origin.clone());
+            info!("Received message `{}` from `{}` ", String::from_utf8_lossy(&message.payload), origin);
+        }
+    });
+    thread::spawn(move || {
+        // shifted by a half of the period, so every round gets exactly one message
+        thread::sleep(time::Duration::from_millis(period as u64 * 500));
+        loop {
+            node_copy.broadcast(random_msg().into_bytes()).map_err(|err| {error!("Error on broadcasting: {}", err)}).unwrap_or(());
+            thread::sleep(time::Duration::new(period as u64, 0));
+        }
+    });
+}
+
+// Resolves on SIGINT or SIGTERM.
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).unwrap();
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {},
+        _ = terminate.recv() => {},
+    };
 }
 
-fn main() {
+#[tokio::main]
+async fn main() {
     let args: Args = Args::parse();
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+    let admin_addr = args.admin_port.map(|port| match args.admin_bind.parse::<IpAddr>() {
+        Ok(ip) => SocketAddr::new(ip, port),
+        Err(err) => {
+            error!("Invalid admin IP `{}`: {}", args.admin_bind, err);
+            std::process::exit(1);
+        }
+    });
 
     let mut cache = PeerCache::new(args.timeout);
-    let self_name = format!("{}:{}", &args.bind, args.port);
-    cache.update_peer(&self_name, true).unwrap();
-    run_saabisu(&self_name, &args.connect, args.period, args.timeout, &cache);
-    run_server(&args.bind, args.port, &args.cert, &args.key, &cache);
+    cache.set_max_drift(args.max_drift);
+    if let Some(tombstone_ttl) = args.tombstone_ttl {
+        cache.set_tombstone_ttl(tombstone_ttl);
+    }
+    cache.set_key(match &args.node_key {
+        Some(path) => NodeKey::load_or_generate(path).unwrap(),
+        None => NodeKey::generate()
+    });
+    let self_id = load_or_generate_id(args.id_file.as_deref()).unwrap();
+    let advertise = args.advertise.clone().unwrap_or_else(|| match args.bind.parse::<IpAddr>() {
+        Ok(ip) => {
+            if ip.is_unspecified() && args.connect.is_empty() {
+                warn!("Advertising unspecified address `{}`, other peers won't reach it, use --advertise", args.bind);
+            }
+            // unspecified IP is replaced with the one the first peer sees us from
+            SocketAddr::new(ip, args.port).to_string()
+        },
+        Err(_) => format!("{}:{}", &args.bind, args.port)
+    });
+    cache.set_self(&self_id, &advertise).unwrap();
+    info!("Node `{}` is advertised as `{}`", self_id, advertise);
+    if !args.tags.is_empty() {
+        cache.set_metadata(args.tags.iter().cloned().collect()).unwrap();
+    }
+    if let Some(state_file) = &args.state_file {
+        match cache.load_snapshot(state_file) {
+            Ok(loaded) => if loaded { info!("Loaded peers from `{}`", state_file) },
+            Err(err) => error!("Error on loading peers: {}", err)
+        };
+        cache.set_snapshot(state_file).unwrap();
+    }
+    let verification = match (args.ca, args.insecure) {
+        (Some(ca), _) => Verification::Ca(ca),
+        (None, true) => Verification::Insecure,
+        (None, false) => Verification::System,
+    };
+    let config = SaabisuConfig {
+        connect: args.connect,
+        resolver: Arc::new(SystemResolver),
+        resolve_period: args.resolve_period,
+        period: args.period,
+        timeout: args.timeout,
+        indirect_probes: args.indirect_probes,
+        suspect_timeout: args.suspect_timeout,
+        sync_period: args.sync_period,
+        fanout: args.fanout,
+        ttl: args.ttl,
+        gossip_fanout: args.gossip_fanout,
+        prefer_stale: args.prefer_stale,
+        tls: TlsConfig { cert: args.cert, key: args.key, verification },
+    };
+    let server_config = ServerConfig {
+        bind: args.bind,
+        port: args.port,
+    };
+    let node = Node::new(&self_id, &cache, &config).unwrap();
+    run_demo(&node, args.period);
+    let mut services = node.run_saabisu();
+    if let Some(addr) = admin_addr {
+        let admin_node = node.clone();
+        tokio::spawn(async move { admin_node.run_admin(addr).await });
+    }
+    tokio::select! {
+        result = node.run_server(&server_config) => if let Err(err) = result {
+            error!("Error on running the server: {}", err);
+            std::process::exit(1);
+        },
+        _ = shutdown_signal() => info!("Shutting down"),
+    };
+    services.shutdown().await;
+    node.leave().await.map_err(|err| {error!("Error on leaving: {}", err)}).unwrap_or(());
 }
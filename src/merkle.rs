@@ -0,0 +1,72 @@
+use crate::p2pcache::{PeerList, PeerState};
+
+use serde_json::to_string as js_to_string;
+
+/// Number of leaves in the tree. Every peer falls into the bucket chosen by the hash of its id.
+pub const BUCKETS: usize = 16;
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+const FNV_PRIME: u64 = 0x100000001b3;
+
+// FNV-1a, which is stable between builds and platforms, unlike the std hasher.
+fn fnv1a(seed: u64, data: &[u8]) -> u64 {
+    data.iter().fold(seed, |hash, byte| (hash ^ *byte as u64).wrapping_mul(FNV_PRIME))
+}
+
+/// Returns the bucket of the peer with the given id.
+pub fn bucket_of(id: &str) -> usize {
+    (fnv1a(FNV_OFFSET, id.as_bytes()) % BUCKETS as u64) as usize
+}
+
+// Fields of the entry, which converge across nodes. Timestamps are re-stamped by every node
+// on its own pings, so they are left out, otherwise converged caches would never have equal digests.
+fn converged_data(peer: &PeerState) -> String {
+    format!("{}\n{}\n{}\n{}\n{}\n{}", peer.id, peer.incarnation, peer.address, peer.left, peer.available,
+        js_to_string(&peer.metadata).unwrap_or_default())
+}
+
+/// Merkle digest of the PeerList: hashes of all buckets and the root hash built over them.
+/// Entries inside the bucket are hashed in the order of ids, so equal lists
+/// always have equal digests. Only id, incarnation, address, leaving, availability and metadata
+/// are hashed, so lists, which differ just in observation timestamps, have equal digests too.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Digest {
+    pub root: u64,
+    pub buckets: Vec<u64>
+}
+
+impl Digest {
+    /// Builds digest from peers, which are expected to be sorted by id.
+    pub fn build<'a, I: Iterator<Item=&'a PeerState>>(peers: I) -> Self {
+        let mut buckets = vec![FNV_OFFSET; BUCKETS];
+        for peer in peers {
+            let bucket = bucket_of(&peer.id);
+            buckets[bucket] = fnv1a(buckets[bucket], converged_data(peer).as_bytes());
+        }
+        let mut level = buckets.clone();
+        while level.len() > 1 {
+            level = level.chunks(2)
+                .map(|pair| pair.iter().fold(FNV_OFFSET, |hash, child| fnv1a(hash, &child.to_le_bytes())))
+                .collect();
+        }
+        Digest { root: level[0], buckets }
+    }
+
+    /// Returns indexes of buckets, which differ from the `other` digest.
+    pub fn diff(&self, other: &Digest) -> Vec<usize> {
+        if self.root == other.root && self.buckets.len() == other.buckets.len() {
+            return vec![];
+        }
+        (0..BUCKETS)
+            .filter(|idx| self.buckets.get(*idx) != other.buckets.get(*idx))
+            .collect()
+    }
+}
+
+/// Request of the anti-entropy round: buckets, which differ, and entries of the sender in them.
+/// Receiver merges the entries and replies with PeerList of its own entries in these buckets.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SyncRequest {
+    pub buckets: Vec<usize>,
+    pub peers: PeerList
+}
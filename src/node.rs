@@ -0,0 +1,275 @@
+use crate::admin::{run_admin, NodeInfo};
+use crate::client::build_client;
+use crate::p2pcache::PeerCache;
+use crate::saabisu::{announce_leave, run_saabisu, SaabisuConfig};
+use crate::server::{run_server, ServerConfig};
+use crate::signing::{verify, NodeKey};
+use crate::stats::{Stats, StatsSnapshot};
+
+use log::{error, info};
+use rand::{distributions::Alphanumeric, Rng};
+use tokio::task::JoinSet;
+
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::{Duration, Instant};
+
+/// How long ids of the received messages are remembered.
+const SEEN_EXPIRY: Duration = Duration::from_secs(300);
+
+// Random alphanumeric id of 16 characters.
+fn random_id() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(16)
+        .map(char::from)
+        .collect()
+}
+
+/// Loads the id of the node from the file or generates new one and saves it, so the node
+/// is known under the same id after restarts, even if its address changes.
+/// Without the file new id is generated.
+pub fn load_or_generate_id(path: Option<&str>) -> Result<String, String> {
+    let path = match path {
+        Some(val) => val,
+        None => return Ok(random_id())
+    };
+    if !Path::new(path).exists() {
+        let id = random_id();
+        fs::write(path, &id).map_err(|err| format!("Error on writing the node id `{}`: {:?}", path, err))?;
+        info!("Generated new node id `{}` in `{}`", id, path);
+        return Ok(id);
+    }
+    let id = fs::read_to_string(path).map_err(|err| format!("Error on reading the node id `{}`: {:?}", path, err))?;
+    let id = id.trim();
+    if id.is_empty() {
+        return Err(format!("Node id `{}` is empty", path));
+    }
+    Ok(id.to_string())
+}
+
+/// Application message gossiped through the cluster. Every message has unique id
+/// and `ttl` - the number of hops it still can be forwarded. Origin signs everything
+/// except the `ttl`, which is decreased by forwarding peers.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Message {
+    pub id: String,
+    pub origin: String,
+    pub ttl: u32,
+    pub payload: Vec<u8>,
+    #[serde(default)]
+    pub key: String,
+    #[serde(default)]
+    pub signature: String
+}
+
+impl Message {
+    /// Creates a message with random id.
+    pub fn new(origin: &str, ttl: u32, payload: Vec<u8>) -> Self {
+        Message { id: random_id(), origin: origin.to_string(), ttl, payload, key: String::new(), signature: String::new() }
+    }
+
+    fn signed_data(&self) -> Vec<u8> {
+        let mut data = format!("{}\n{}\n{}\n", self.id, self.origin, self.key).into_bytes();
+        data.extend(&self.payload);
+        data
+    }
+
+    /// Signs the message with the key of the origin.
+    pub fn sign(&mut self, key: &NodeKey) {
+        self.key = key.public();
+        self.signature = key.sign(&self.signed_data());
+    }
+
+    /// Returns true if the message is signed by the owner of its `key`.
+    pub fn is_signed(&self) -> bool {
+        verify(&self.key, &self.signed_data(), &self.signature)
+    }
+}
+
+/// Ids of recently seen messages, used for suppressing duplicates.
+#[derive(Debug, Clone, Default)]
+pub struct SeenCache {
+    seen: Arc<Mutex<HashMap<String, Instant>>>
+}
+
+impl SeenCache {
+    pub fn new() -> Self {
+        SeenCache::default()
+    }
+
+    /// Remembers the id and returns true if it hasn't been seen yet. Also forgets expired ids.
+    pub fn insert(&self, id: &str) -> Result<bool, String> {
+        self.seen.lock().map(|mut seen| {
+            let now = Instant::now();
+            seen.retain(|_, since| now.duration_since(*since) < SEEN_EXPIRY);
+            seen.insert(id.to_string(), now).is_none()
+        }).map_err(|err| { error!("Poison error: {:?}", err); format!("Poison error: {:?}", err) })
+    }
+}
+
+/// Delivers received messages to all subscribers, skipping already seen ones.
+#[derive(Debug, Clone, Default)]
+pub struct Inbox {
+    subscribers: Arc<Mutex<Vec<Sender<Message>>>>,
+    seen: SeenCache
+}
+
+impl Inbox {
+    pub fn new() -> Self {
+        Inbox::default()
+    }
+
+    /// Returns receiver, which gets every message received after the subscription.
+    pub fn subscribe(&self) -> Result<Receiver<Message>, String> {
+        let (sender, receiver) = channel();
+        self.subscribers.lock().map(|mut subscribers| {
+            subscribers.push(sender);
+        }).map_err(|err| { error!("Poison error: {:?}", err); format!("Poison error: {:?}", err) })?;
+        Ok(receiver)
+    }
+
+    /// Sends the message to all subscribers, if it hasn't been seen before, and returns true,
+    /// otherwise returns false. Subscribers with dropped receivers are removed.
+    pub fn deliver(&self, message: &Message) -> Result<bool, String> {
+        if !self.seen.insert(&message.id)? {
+            return Ok(false);
+        }
+        self.subscribers.lock().map(|mut subscribers| {
+            subscribers.retain(|sender| sender.send(message.clone()).is_ok());
+        }).map_err(|err| { error!("Poison error: {:?}", err); format!("Poison error: {:?}", err) })?;
+        Ok(true)
+    }
+
+    /// Marks the message as seen without delivering it, used for own messages.
+    pub fn mark_seen(&self, id: &str) -> Result<(), String> {
+        self.seen.insert(id).map(|_| ())
+    }
+}
+
+/// Queue of messages, which are sent to the peers on the next messenger round.
+#[derive(Debug, Clone, Default)]
+pub struct Outbox {
+    queue: Arc<Mutex<VecDeque<Message>>>
+}
+
+impl Outbox {
+    pub fn new() -> Self {
+        Outbox::default()
+    }
+
+    pub fn push(&self, message: Message) -> Result<(), String> {
+        self.queue.lock().map(|mut queue| {
+            queue.push_back(message);
+        }).map_err(|err| { error!("Poison error: {:?}", err); format!("Poison error: {:?}", err) })
+    }
+
+    /// Takes all queued messages.
+    pub fn drain(&self) -> Result<Vec<Message>, String> {
+        self.queue.lock().map(|mut queue| {
+            queue.drain(..).collect()
+        }).map_err(|err| { error!("Poison error: {:?}", err); format!("Poison error: {:?}", err) })
+    }
+}
+
+/// Public entry point of the gossip node: runs services and server, broadcasts
+/// application payloads to the cluster and delivers received ones to subscribers.
+/// Messages are spread epidemically: every node forwards new message to `fanout`
+/// random peers until its `ttl` is exhausted. All requests of the node go through
+/// one pooled client, so connections to peers are reused.
+#[derive(Debug, Clone)]
+pub struct Node {
+    id: String,
+    cache: PeerCache,
+    config: SaabisuConfig,
+    inbox: Inbox,
+    outbox: Outbox,
+    client: reqwest::Client,
+    stats: Arc<Stats>,
+    started: Instant,
+}
+
+impl Node {
+    pub fn new(id: &str, cache: &PeerCache, config: &SaabisuConfig) -> Result<Self, String> {
+        Ok(Node {
+            id: id.to_string(),
+            cache: cache.clone(),
+            config: config.clone(),
+            inbox: Inbox::new(),
+            outbox: Outbox::new(),
+            client: build_client(&config.tls)?,
+            stats: Arc::new(Stats::new()),
+            started: Instant::now(),
+        })
+    }
+
+    /// Returns the id of the node, which is the origin of its messages.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn cache(&self) -> &PeerCache {
+        &self.cache
+    }
+
+    /// Returns counters of the gossip activity of the node.
+    pub fn stats(&self) -> StatsSnapshot {
+        self.stats.snapshot()
+    }
+
+    /// Returns the id, version, uptime, own address and options of the node.
+    pub fn info(&self) -> Result<NodeInfo, String> {
+        Ok(NodeInfo {
+            id: self.id.clone(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            uptime: self.started.elapsed().as_secs(),
+            address: self.cache.address_of(&self.id)?,
+            config: self.config.clone(),
+        })
+    }
+
+    /// Queues the payload to be spread through the cluster on the next round.
+    /// Message is signed with the node key of the cache, if it's set.
+    pub fn broadcast(&self, payload: Vec<u8>) -> Result<(), String> {
+        let mut message = Message::new(&self.id, self.config.ttl, payload);
+        if let Some(key) = self.cache.node_key() {
+            message.sign(key);
+        }
+        self.inbox.mark_seen(&message.id)?;
+        self.outbox.push(message)
+    }
+
+    /// Returns receiver of the messages from other peers.
+    pub fn subscribe(&self) -> Result<Receiver<Message>, String> {
+        self.inbox.subscribe()
+    }
+
+    /// Starts gossiping services as tasks on the current tokio runtime.
+    /// Services stop when the returned set is shut down or dropped.
+    pub fn run_saabisu(&self) -> JoinSet<()> {
+        run_saabisu(&self.id, &self.config, &self.cache, &self.outbox, &self.client, &self.stats)
+    }
+
+    /// Runs the server until the runtime is shut down or the future is dropped.
+    /// Returns an error if the server couldn't be started.
+    pub async fn run_server(&self, config: &ServerConfig) -> Result<(), String> {
+        run_server(config, &self.config.tls, &self.cache, &self.inbox, &self.client, self.config.fanout, &self.stats).await
+    }
+
+    /// Runs the read-only admin API on `addr` until the runtime is shut down or the future is dropped.
+    pub async fn run_admin(&self, addr: SocketAddr) {
+        run_admin(addr, self).await;
+    }
+
+    /// Announces other peers that the node has left the cluster. Should be called after services
+    /// are stopped, so the node doesn't refute its leaving.
+    pub async fn leave(&self) -> Result<(), String> {
+        let announced = announce_leave(&self.client, &mut self.cache.clone(), self.config.gossip_fanout).await?;
+        info!("Leaving has been announced to {} peers", announced);
+        Ok(())
+    }
+}
@@ -1,26 +1,97 @@
 use crate::waiter::Waiter;
+use crate::hlc::Hlc;
 
-use log::{error};
-#[cfg(not(feature = "mock_time"))]
-use chrono::prelude::*;
+use crate::clock::{Clock, SystemClock};
+use crate::merkle::{bucket_of, Digest};
+use crate::signing::{verify, NodeKey};
+
+use log::{error, info, trace, warn};
+use rand::seq::SliceRandom;
+
+use serde_json::{from_str as js_from_str, to_string as js_to_string};
 
 use std::collections::BTreeMap;
-use std::sync::{Arc, RwLock};
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, RwLock};
 
 const MS_IN_SEC: u32 = 1000;
+const DEFAULT_MAX_DRIFT: u32 = 60;
 
-/// Represents a peer - it has address, timestamp of last request and availability,
-/// which shows last connection result.
+/// Represents a peer - it has persistent id, which the peer is known by, advertised address,
+/// which may change between restarts, hybrid logical timestamp of last request and availability,
+/// which shows last connection result. Peer, that failed both direct and indirect probes,
+/// is marked as `suspect` and remains available until the suspicion times out.
+/// Incarnation can be increased only by the peer itself, and is used to refute
+/// suspicions about it: state with higher incarnation always wins.
+/// Peer, which has gracefully left the cluster, announces itself as `left` with higher incarnation,
+/// so it's distinguishable from the failed ones.
+/// Peer can publish arbitrary `metadata` (role, version, zone, ports and so on), every change of which
+/// increases its incarnation, so the newest metadata always wins.
+/// Peer signs its id, address, incarnation, public `key`, leaving and metadata with its node key,
+/// so others can't forge entries about it. Timestamp and availability are observations of other peers
+/// and aren't signed.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PeerState {
+    pub id: String,
     pub address: String,
-    pub timestamp: i64,
-    pub available: bool
+    pub timestamp: Hlc,
+    pub available: bool,
+    #[serde(default)]
+    pub suspect: bool,
+    #[serde(default)]
+    pub incarnation: u64,
+    #[serde(default)]
+    pub left: bool,
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+    #[serde(default)]
+    pub key: String,
+    #[serde(default)]
+    pub signature: String
+}
+
+impl PeerState {
+    pub fn new<T: Into<Hlc>>(id: &str, address: &str, timestamp: T, available: bool) -> Self {
+        PeerState { id: id.to_string(), address: address.to_string(), timestamp: timestamp.into(), available,
+            suspect: false, incarnation: 0, left: false,
+            metadata: BTreeMap::new(), key: String::new(), signature: String::new() }
+    }
+
+    fn signed_data(&self) -> Vec<u8> {
+        let left = if self.left { "\nleft" } else { "" };
+        let mut data = format!("{}\n{}\n{}\n{}{}", self.id, self.address, self.incarnation, self.key, left);
+        if !self.metadata.is_empty() {
+            data.push('\n');
+            data.push_str(&js_to_string(&self.metadata).unwrap_or_default());
+        }
+        data.into_bytes()
+    }
+
+    /// Signs the entry with the key of the node, which it describes.
+    pub fn sign(&mut self, key: &NodeKey) {
+        self.key = key.public();
+        self.signature = key.sign(&self.signed_data());
+    }
+
+    /// Returns true if the entry is signed by the owner of its `key`.
+    pub fn is_signed(&self) -> bool {
+        verify(&self.key, &self.signed_data(), &self.signature)
+    }
+
+    // Returns true if this state should be replaced by the `other` one.
+    fn is_older_than(&self, other: &PeerState) -> bool {
+        (self.incarnation, self.timestamp) <= (other.incarnation, other.timestamp)
+    }
 }
 
 impl PartialEq for PeerState {
     fn eq(&self, other: &Self) -> bool {
-        self.address == other.address && self.available == self.available && self.timestamp == other.timestamp
+        self.id == other.id && self.address == other.address && self.available == other.available
+            && self.suspect == other.suspect && self.timestamp == other.timestamp
+            && self.incarnation == other.incarnation && self.left == other.left && self.metadata == other.metadata
+            && self.key == other.key && self.signature == other.signature
     }
 }
 
@@ -30,64 +101,299 @@ pub struct PeerList {
     pub peers: Vec<PeerState>
 }
 
+/// Filter of the peers for service discovery, every set condition must hold.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct PeerQuery {
+    /// `key:value` - peer has metadata `key` equal to `value`, or just `key` - peer has metadata `key`
+    pub tag: Option<String>,
+    /// Availability of the peer
+    pub available: Option<bool>,
+    /// Maximal number of seconds since the peer has been seen last time
+    pub max_age: Option<u32>,
+}
+
+impl PeerQuery {
+    /// Returns true if the peer matches the query at the time `now` in milliseconds.
+    pub fn matches(&self, peer: &PeerState, now: i64) -> bool {
+        let tag_matches = self.tag.as_ref().is_none_or(|tag| match tag.split_once(':') {
+            Some((key, value)) => peer.metadata.get(key).is_some_and(|val| val == value),
+            None => peer.metadata.contains_key(tag)
+        });
+        tag_matches
+            && self.available.is_none_or(|available| peer.available == available)
+            && self.max_age.is_none_or(|max_age|
+                now.saturating_sub(peer.timestamp.wall) <= i64::from(max_age) * MS_IN_SEC as i64)
+    }
+}
+
+// Tracks local version of every entry, so only entries changed since some version can be sent.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct ChangeLog {
+    pub version: u64,
+    pub versions: BTreeMap<String, u64>
+}
+
+impl ChangeLog {
+    fn touch(&mut self, id: &str) {
+        self.version += 1;
+        self.versions.insert(id.to_string(), self.version);
+    }
+}
+
+// Removed peer, whose stale entries are rejected until `expires`. Only entries with higher
+// incarnation or alive entries with timestamps newer than the removed one bring the peer back.
+// Incarnation of the alive entry isn't compared, since the node restarted with the same id
+// begins with incarnation 0 again, while the tombstone may keep a higher one after its leaving.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Tombstone {
+    pub incarnation: u64,
+    pub timestamp: Hlc,
+    pub expires: i64
+}
+
+impl Tombstone {
+    fn is_outdated_by(&self, peer: &PeerState) -> bool {
+        peer.incarnation > self.incarnation
+            || (peer.available && peer.timestamp > self.timestamp)
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct PeerMap {
-    pub peers: BTreeMap<String, PeerState>
+    pub peers: BTreeMap<String, PeerState>,
+    pub self_id: Option<String>,
+    pub hlc: Hlc,
+    pub changes: ChangeLog,
+    // public keys, pinned on the first signed entry of the peer
+    pub keys: BTreeMap<String, String>,
+    #[serde(default)]
+    pub tombstones: BTreeMap<String, Tombstone>
 }
 
 /// PeerCache stores lists of peers with it's states and timestamps,
-/// and manages updates. Also contains signaler, which can be used
+/// and manages updates. Timestamps are issued by hybrid logical clock, which is advanced
+/// on every received PeerList, and entries from peers, whose clock is ahead by more than
+/// `max_drift` seconds, are rejected. Also contains signaler, which can be used
 /// for waiting/signaling purposes externally.
-/// For testing purposes, has feature `mock_time`, which make it possible
-/// to manage timestamps within the tests.
+/// Physical time is taken from the `Clock`, which is shared by all clones of the cache,
+/// so tests and simulations can use `ManualClock` or `OffsetClock` instead of the system one.
+/// If snapshot file is set, the list is saved to it on every change.
+/// If node key is set, own entry is signed with it, and only entries signed with the key,
+/// which has been pinned on the first signed entry of the peer, are accepted from others.
 #[derive(Debug, Clone)]
 pub struct PeerCache {
     peers: Arc<RwLock<PeerMap>>,
+    clock: Arc<dyn Clock>,
+    snapshot: Arc<Mutex<Option<PathBuf>>>,
+    key: Option<NodeKey>,
     timeout: u32,
+    tombstone_ttl: u32,
+    max_drift: u32,
     pub signaler: Waiter,
-    #[cfg(feature = "mock_time")]
-    pub current_time: i64
 }
 
 
 impl PeerCache {
-    #[cfg(not(feature = "mock_time"))]
     pub fn new(timeout: u32) -> Self {
-        PeerCache { peers: Arc::new(RwLock::new(PeerMap { peers: BTreeMap::new() })), timeout: timeout*MS_IN_SEC, signaler: Waiter::new(), }
+        PeerCache::with_clock(timeout, Arc::new(SystemClock))
     }
-    #[cfg(feature = "mock_time")]
-    pub fn new(timeout: u32) -> Self {
-        PeerCache { peers: Arc::new(RwLock::new(PeerMap { peers: BTreeMap::new() })),
+
+    pub fn with_clock(timeout: u32, clock: Arc<dyn Clock>) -> Self {
+        PeerCache { peers: Arc::new(RwLock::new(PeerMap { peers: BTreeMap::new(), self_id: None, hlc: Hlc::default(),
+                changes: ChangeLog::default(), keys: BTreeMap::new(), tombstones: BTreeMap::new() })),
+            clock,
+            snapshot: Arc::new(Mutex::new(None)),
+            key: None,
             timeout: timeout*MS_IN_SEC,
+            tombstone_ttl: 2*timeout*MS_IN_SEC,
+            max_drift: DEFAULT_MAX_DRIFT*MS_IN_SEC,
             signaler: Waiter::new(),
-            current_time: 0,
         }
     }
 
-    #[cfg(not(feature = "mock_time"))]
     fn timestamp_now(&self) -> i64 {
-        Utc::now().timestamp_millis()
+        self.clock.now()
     }
-    #[cfg(feature = "mock_time")]
-    fn timestamp_now(&self) -> i64 {
-        self.current_time
+
+    /// Sets maximal allowed difference in seconds between local clock and timestamps of incoming entries.
+    pub fn set_max_drift(&mut self, max_drift: u32) {
+        self.max_drift = max_drift*MS_IN_SEC;
+    }
+
+    /// Sets how many seconds removed peers are remembered, so their stale entries from other peers
+    /// don't bring them back. Should be longer than the time the cluster needs to remove the peer,
+    /// by default it's twice the `timeout`.
+    pub fn set_tombstone_ttl(&mut self, tombstone_ttl: u32) {
+        self.tombstone_ttl = tombstone_ttl*MS_IN_SEC;
+    }
+
+    /// Sets the key of the node, which own entry is signed with, and enables verification
+    /// of the incoming entries. Should be set before `set_self`.
+    pub fn set_key(&mut self, key: NodeKey) {
+        self.key = Some(key);
+    }
+
+    /// Returns the key of the node, if it has been set.
+    pub fn node_key(&self) -> Option<&NodeKey> {
+        self.key.as_ref()
+    }
+
+    /// Returns the public key pinned for the peer.
+    pub fn key_of(&self, id: &str) -> Result<Option<String>, String> {
+        self.peers.read().map(|cache| {
+            cache.keys.get(id).cloned()
+        }).map_err(|err| { error!("Poison error: {:?}", err); format!("Poison error: {:?}", err) })
+    }
+
+    /// Sets the file, where the list is saved on every change, and saves current list to it.
+    pub fn set_snapshot(&mut self, path: &str) -> Result<(), String> {
+        self.snapshot.lock().map(|mut snapshot| {
+            *snapshot = Some(PathBuf::from(path));
+        }).map_err(|err| { error!("Poison error: {:?}", err); format!("Poison error: {:?}", err) })?;
+        self.save_snapshot()
+    }
+
+    /// Loads peers from the snapshot file, if it exists, and merges them into the list.
+    /// Returns true if the list has changed.
+    pub fn load_snapshot(&mut self, path: &str) -> Result<bool, String> {
+        if !Path::new(path).exists() {
+            return Ok(false);
+        }
+        let peers: PeerList = fs::read_to_string(path)
+            .map_err(|err| format!("Error on reading snapshot `{}`: {:?}", path, err))
+            .and_then(|data| js_from_str(&data).map_err(|err| format!("Error on parsing snapshot `{}`: {:?}", path, err)))?;
+        self.update_from_list(&peers)
     }
 
-    #[cfg(feature = "mock_time")]
-    pub fn set_current_time(&mut self, time: i64) {
-        self.current_time = time;
+    // Writes the list to the temporary file and moves it to the snapshot path,
+    // so the snapshot is never left half-written.
+    fn save_snapshot(&self) -> Result<(), String> {
+        let snapshot = self.snapshot.lock()
+            .map_err(|err| { error!("Poison error: {:?}", err); format!("Poison error: {:?}", err) })?;
+        let path = match snapshot.as_ref() {
+            Some(val) => val,
+            None => return Ok(())
+        };
+        let data = js_to_string(&self.get_list()?).map_err(|err| format!("Error on jsoning the PeerList: {:?}", err))?;
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, data)
+            .and_then(|_| fs::rename(&tmp_path, path))
+            .map_err(|err| format!("Error on writing snapshot `{:?}`: {:?}", path, err))
+    }
+
+    fn save_on_change(&self, changed: bool) -> Result<bool, String> {
+        if changed {
+            self.save_snapshot().map_err(|err| error!("{}", err)).unwrap_or(());
+        }
+        Ok(changed)
     }
 
-    /// Removes peers, that couldn't be connected for `timeout` seconds.
+    /// Registers the id and the advertised address of current node, so it can refute suspicions
+    /// about itself, and marks it as available.
+    pub fn set_self(&mut self, id: &str, address: &str) -> Result<bool, String> {
+        self.peers.write().map(|mut cache| {
+            cache.self_id = Some(id.to_string());
+        }).map_err(|err| { error!("Poison error: {:?}", err); format!("Poison error: {:?}", err) })?;
+        self.update_peer(id, address, true)
+    }
+
+    /// Replaces the unspecified IP (`0.0.0.0` or `::`) in the address of current node with the IP
+    /// it's `observed` from by another peer, keeping own port. Returns true if the address has changed.
+    pub fn adopt_observed(&mut self, observed: &str) -> Result<bool, String> {
+        let own = match self.self_state()? {
+            Some(val) => val,
+            None => return Ok(false)
+        };
+        let (own_addr, observed) = match (own.address.parse::<SocketAddr>(), observed.parse::<SocketAddr>()) {
+            (Ok(own_addr), Ok(observed)) => (own_addr, observed),
+            _ => return Ok(false)
+        };
+        if !own_addr.ip().is_unspecified() || observed.ip().is_unspecified() {
+            return Ok(false);
+        }
+        let address = SocketAddr::new(observed.ip(), own_addr.port()).to_string();
+        info!("Discovered own address `{}`", address);
+        self.update_peer(&own.id, &address, true)
+    }
+
+    /// Replaces metadata of current node and increases its incarnation, so the new metadata
+    /// wins over the old one in other caches. Returns true if the metadata has changed.
+    pub fn set_metadata(&mut self, metadata: BTreeMap<String, String>) -> Result<bool, String> {
+        let now = self.timestamp_now();
+        let mut changed = false;
+        self.peers.write().map(|mut cache| {
+            let PeerMap { peers, self_id, hlc, changes, .. } = &mut *cache;
+            if let Some(own) = self_id.as_ref().and_then(|id| peers.get_mut(id)) {
+                if own.metadata != metadata {
+                    own.metadata = metadata;
+                    own.incarnation += 1;
+                    own.timestamp = hlc.tick(now);
+                    if let Some(key) = &self.key {
+                        own.sign(key);
+                    }
+                    changes.touch(&own.id);
+                    changed = true;
+                }
+            }
+        }).map_err(|err| { error!("Poison error: {:?}", err); format!("Poison error: {:?}", err) })?;
+        self.save_on_change(changed)
+    }
+
+    /// Returns metadata of the peer.
+    pub fn metadata_of(&self, id: &str) -> Result<Option<BTreeMap<String, String>>, String> {
+        self.peers.read().map(|cache| {
+            cache.peers.get(id).map(|val| val.metadata.clone())
+        }).map_err(|err| { error!("Poison error: {:?}", err); format!("Poison error: {:?}", err) })
+    }
+
+    /// Marks current node as left with higher incarnation, so its entry wins over any other state
+    /// about it, and returns the entry, which should be sent to other peers.
+    /// After that the node doesn't refute anything about itself.
+    pub fn leave(&mut self) -> Result<Option<PeerState>, String> {
+        let now = self.timestamp_now();
+        let own = self.peers.write().map(|mut cache| {
+            let PeerMap { peers, self_id, hlc, changes, .. } = &mut *cache;
+            let own = self_id.as_ref().and_then(|id| peers.get_mut(id))?;
+            own.left = true;
+            own.available = false;
+            own.suspect = false;
+            own.incarnation += 1;
+            own.timestamp = hlc.tick(now);
+            if let Some(key) = &self.key {
+                own.sign(key);
+            }
+            changes.touch(&own.id);
+            Some(own.clone())
+        }).map_err(|err| { error!("Poison error: {:?}", err); format!("Poison error: {:?}", err) })?;
+        self.save_on_change(own.is_some())?;
+        Ok(own)
+    }
+
+    /// Removes peers, that couldn't be connected for `timeout` seconds, and leaves tombstones
+    /// instead of them, which are dropped after `tombstone_ttl` seconds together with pinned keys.
     pub fn cleanup_old_peers(&mut self) -> Result<(), String> {
         let current_utc = self.timestamp_now();
+        let mut changed = false;
         self.peers.write().map(|mut cache| {
-            for peer in &cache.peers.values().map(|val| val.clone()).collect::<Vec<PeerState>>() {
-                if !peer.available && current_utc - peer.timestamp > self.timeout as i64 {
-                    cache.peers.remove(&peer.address);
+            let PeerMap { peers, changes, keys, tombstones, .. } = &mut *cache;
+            for peer in &peers.values().cloned().collect::<Vec<PeerState>>() {
+                if !peer.available && current_utc.saturating_sub(peer.timestamp.wall) > self.timeout as i64 {
+                    peers.remove(&peer.id);
+                    changes.versions.remove(&peer.id);
+                    tombstones.insert(peer.id.clone(), Tombstone { incarnation: peer.incarnation, timestamp: peer.timestamp,
+                        expires: current_utc + self.tombstone_ttl as i64 });
+                    changed = true;
                 }
             }
+            tombstones.retain(|id, tombstone| {
+                let alive = tombstone.expires > current_utc;
+                if !alive && !peers.contains_key(id) {
+                    keys.remove(id);
+                }
+                alive
+            });
         }).map_err(|err| { error!("Poison error: {:?}", err); format!("Poison error: {:?}", err) })?;
+        self.save_on_change(changed)?;
         Ok(())
     }
 
@@ -95,61 +401,276 @@ impl PeerCache {
     /// and returns Result with bool, which means:
     ///  * true - peer list has changed and need to be sent to other peers
     ///  * false - peer remains the same and no resync is required
+    ///
     /// List is considered to be changed when either a new item has been inserted
-    /// or state of the existing one has been changed. Also, in any case, updates timestamp.
-    pub fn update_peer(&mut self, address: &str, available: bool) -> Result<bool, String> {
+    /// or state of the existing one has been changed. Also, in any case, updates timestamp,
+    /// but only changed entries get into the deltas.
+    /// Any update clears the suspicion of the peer, incarnation and signature remain the same.
+    /// Address is changed only for the new peers and for the current node, whose entry is signed
+    /// with the node key. Peers, which have left, are updated only by themselves.
+    pub fn update_peer(&mut self, id: &str, address: &str, available: bool) -> Result<bool, String> {
         let mut changed = false;
         self.peers.write().map(|mut cache| {
-            match cache.peers.get(address) {
+            let is_self = cache.self_id.as_deref() == Some(id);
+            let mut state = PeerState::new(id, address, 0, available);
+            match cache.peers.get(id) {
+                Some(val) if val.left && !is_self => return,
                 Some(val) => {
-                    if val.available != available || available {
-                        changed = val.available != available;
+                    changed = val.available != available || val.suspect || (is_self && val.address != address);
+                    if !is_self {
+                        state.address = val.address.clone();
                     }
+                    state.incarnation = val.incarnation;
+                    state.left = val.left;
+                    state.metadata = val.metadata.clone();
+                    state.key = val.key.clone();
+                    state.signature = val.signature.clone();
                 },
                 None => {
                     changed = true;
                 }
             };
             if changed || available {
-                cache.peers.insert(address.to_string(), PeerState { address: address.to_string(), available, timestamp: self.timestamp_now() });
+                state.timestamp = cache.hlc.tick(self.timestamp_now());
+                if let (Some(key), true) = (&self.key, is_self) {
+                    state.sign(key);
+                    cache.keys.insert(id.to_string(), state.key.clone());
+                }
+                cache.peers.insert(id.to_string(), state);
+                if changed {
+                    cache.changes.touch(id);
+                }
             }
         }).map_err(|err| { error!("Poison error: {:?}", err); format!("Poison error: {:?}", err) })?;
-        Ok(changed)
+        self.save_on_change(changed)
+    }
+
+    /// Marks available peer as suspected to be failed and returns Result with bool, which means:
+    ///  * true - peer has become suspect and list need to be sent to other peers
+    ///  * false - peer is unknown, unavailable or is already suspected
+    ///
+    /// Timestamp is updated only on the first suspicion, so it shows when the suspicion has started.
+    pub fn suspect_peer(&mut self, id: &str) -> Result<bool, String> {
+        let mut changed = false;
+        let now = self.timestamp_now();
+        self.peers.write().map(|mut cache| {
+            let PeerMap { peers, hlc, changes, .. } = &mut *cache;
+            if let Some(val) = peers.get_mut(id) {
+                if val.available && !val.suspect {
+                    val.suspect = true;
+                    val.timestamp = hlc.tick(now);
+                    changes.touch(id);
+                    changed = true;
+                }
+            }
+        }).map_err(|err| { error!("Poison error: {:?}", err); format!("Poison error: {:?}", err) })?;
+        self.save_on_change(changed)
+    }
+
+    /// Marks peers, that have been suspected for more than `suspect_timeout` seconds, as unavailable.
+    /// Returns true if any of the peers has been marked.
+    pub fn expire_suspects(&mut self, suspect_timeout: u32) -> Result<bool, String> {
+        let mut changed = false;
+        let now = self.timestamp_now();
+        self.peers.write().map(|mut cache| {
+            let PeerMap { peers, hlc, changes, .. } = &mut *cache;
+            for val in peers.values_mut() {
+                if val.suspect && now.saturating_sub(val.timestamp.wall) > i64::from(suspect_timeout) * MS_IN_SEC as i64 {
+                    val.available = false;
+                    val.suspect = false;
+                    val.timestamp = hlc.tick(now);
+                    changes.touch(&val.id);
+                    changed = true;
+                }
+            }
+        }).map_err(|err| { error!("Poison error: {:?}", err); format!("Poison error: {:?}", err) })?;
+        self.save_on_change(changed)
     }
 
     /// Updates current list from incoming PeerList
     /// and returns Result with bool, which means:
     ///  * true - peer list has changed and need to be sent to other peers
     ///  * false - peer remains the same and no resync is required
+    ///
     /// List is considered to be changed when either a new items have been inserted
-    /// or state of the existing ones have been changed.
-    /// For this method, timestamps does matter as the only newer entries are considered.
+    /// or state of the existing ones have been changed. Only changed entries get into the deltas,
+    /// entries, which are merely newer, just refresh the timestamps.
+    /// For this method, incarnations and timestamps does matter as the only newer entries are considered.
+    /// Entries about the current node are never accepted: if they claim it to be suspected
+    /// or unavailable, or have an old address, current node refutes them by increasing own incarnation,
+    /// unless it has left.
+    /// Entries with timestamps ahead of local clock by more than `max_drift`, with timestamps too far
+    /// in the past to compare with local clock or with exhausted logical counter are skipped, as well as
+    /// stale entries of the removed peers.
+    /// If node key is set, unsigned entries, entries with wrong signatures and entries signed
+    /// with a key other than the pinned one are skipped too. Signed entry always replaces
+    /// unsigned local one, which has been added on a direct contact with the peer.
     pub fn update_from_list(&mut self, other: &PeerList) -> Result<bool, String> {
         let mut changed = false;
+        let now = self.timestamp_now();
         self.peers.write().map(|mut cache| {
+            let PeerMap { peers, self_id, hlc, changes, keys, tombstones } = &mut *cache;
             for peer in &other.peers {
-                match cache.peers.get(&peer.address) {
+                if peer.timestamp.wall.checked_sub(now).is_none_or(|ahead| ahead > self.max_drift as i64)
+                    || peer.timestamp.logical == u32::MAX {
+                    warn!("Skipping `{}` with invalid timestamp {}", peer.id, peer.timestamp);
+                    continue;
+                }
+                if self.key.is_some() {
+                    if peer.signature.is_empty() {
+                        trace!("Skipping unsigned `{}`", peer.id);
+                        continue;
+                    }
+                    if !peer.is_signed() {
+                        warn!("Skipping `{}` with wrong signature", peer.id);
+                        continue;
+                    }
+                    if keys.get(&peer.id).is_some_and(|key| *key != peer.key) {
+                        warn!("Skipping `{}` signed with other key than the pinned one", peer.id);
+                        continue;
+                    }
+                }
+                hlc.observe(&peer.timestamp, now);
+                if self_id.as_ref() == Some(&peer.id) {
+                    if let Some(own) = peers.get_mut(&peer.id) {
+                        if !own.left && (peer.incarnation > own.incarnation
+                            || (peer.incarnation == own.incarnation
+                                && (!peer.available || peer.suspect || peer.address != own.address))) {
+                            own.incarnation = peer.incarnation + 1;
+                            own.timestamp = hlc.tick(now);
+                            own.available = true;
+                            own.suspect = false;
+                            if let Some(key) = &self.key {
+                                own.sign(key);
+                            }
+                            changes.touch(&peer.id);
+                            changed = true;
+                        }
+                    }
+                    continue;
+                }
+                let peer_changed = match peers.get(&peer.id) {
                     Some(val) => {
-                        if val.timestamp > peer.timestamp { // skip old data
+                        let upgrade = self.key.is_some() && val.signature.is_empty();
+                        if !upgrade && !val.is_older_than(peer) { // skip old data
                             continue;
                         }
-                        changed = val.available != peer.available;
+                        upgrade || val.available != peer.available || val.suspect != peer.suspect
+                            || val.incarnation != peer.incarnation || val.address != peer.address
+                            || val.left != peer.left || val.metadata != peer.metadata
                     },
                     None => {
-                        changed = true;
+                        if tombstones.get(&peer.id).is_some_and(|tombstone| !tombstone.is_outdated_by(peer)) {
+                            trace!("Skipping removed `{}`", peer.id);
+                            continue;
+                        }
+                        tombstones.remove(&peer.id);
+                        true
                     }
                 };
-                cache.peers.insert(peer.address.clone(), peer.clone()); // update in any case
+                if peer.left && !peers.get(&peer.id).is_some_and(|val| val.left) {
+                    info!("Peer `{}` has left", peer.id);
+                }
+                peers.insert(peer.id.clone(), peer.clone()); // update in any case
+                if peer_changed {
+                    changes.touch(&peer.id);
+                    changed = true;
+                }
+                if self.key.is_some() {
+                    keys.insert(peer.id.clone(), peer.key.clone());
+                }
             }
         }).map_err(|err| { error!("Poison error: {:?}", err); format!("Poison error: {:?}", err) })?;
-        Ok(changed)
+        self.save_on_change(changed)
     }
 
     /// Returns PeerList of peers.
     pub fn get_list(&self) -> Result<PeerList, String> {
         self.peers.read().map(|cache| {
-            Ok(PeerList{peers: cache.peers.values().map(|val| val.clone()).collect::<Vec<PeerState>>()})
+            Ok(PeerList{peers: cache.peers.values().cloned().collect::<Vec<PeerState>>()})
         }).map_err(|err| { error!("Poison error: {:?}", err); format!("Poison error: {:?}", err) })?
     }
 
+    /// Returns PeerList of peers matching the query.
+    pub fn query(&self, query: &PeerQuery) -> Result<PeerList, String> {
+        let now = self.timestamp_now();
+        self.peers.read().map(|cache| {
+            PeerList { peers: cache.peers.values().filter(|val| query.matches(val, now)).cloned().collect() }
+        }).map_err(|err| { error!("Poison error: {:?}", err); format!("Poison error: {:?}", err) })
+    }
+
+    /// Returns PeerList of peers, which have been changed after the local `version`,
+    /// and the current version, which should be used for the next request.
+    /// Version 0 means the full list.
+    pub fn get_delta(&self, version: u64) -> Result<(PeerList, u64), String> {
+        self.peers.read().map(|cache| {
+            let peers = cache.peers.values()
+                .filter(|val| cache.changes.versions.get(&val.id).is_some_and(|ver| *ver > version))
+                .cloned()
+                .collect::<Vec<PeerState>>();
+            (PeerList { peers }, cache.changes.version)
+        }).map_err(|err| { error!("Poison error: {:?}", err); format!("Poison error: {:?}", err) })
+    }
+
+    /// Returns the current local version of the list.
+    pub fn version(&self) -> Result<u64, String> {
+        self.peers.read().map(|cache| cache.changes.version)
+            .map_err(|err| { error!("Poison error: {:?}", err); format!("Poison error: {:?}", err) })
+    }
+
+    /// Returns Merkle digest of the current list, used for anti-entropy.
+    pub fn digest(&self) -> Result<Digest, String> {
+        self.peers.read().map(|cache| {
+            Digest::build(cache.peers.values())
+        }).map_err(|err| { error!("Poison error: {:?}", err); format!("Poison error: {:?}", err) })
+    }
+
+    /// Returns PeerList of peers, which fall into the given buckets of the digest.
+    pub fn get_buckets(&self, buckets: &[usize]) -> Result<PeerList, String> {
+        self.peers.read().map(|cache| {
+            PeerList { peers: cache.peers.values()
+                .filter(|val| buckets.contains(&bucket_of(&val.id)))
+                .cloned()
+                .collect::<Vec<PeerState>>() }
+        }).map_err(|err| { error!("Poison error: {:?}", err); format!("Poison error: {:?}", err) })
+    }
+
+    /// Returns the id of current node, if it has been set.
+    pub fn self_id(&self) -> Result<Option<String>, String> {
+        self.peers.read().map(|cache| {
+            cache.self_id.clone()
+        }).map_err(|err| { error!("Poison error: {:?}", err); format!("Poison error: {:?}", err) })
+    }
+
+    /// Returns the entry of current node, if it has been set.
+    pub fn self_state(&self) -> Result<Option<PeerState>, String> {
+        self.peers.read().map(|cache| {
+            cache.self_id.as_ref().and_then(|id| cache.peers.get(id)).cloned()
+        }).map_err(|err| { error!("Poison error: {:?}", err); format!("Poison error: {:?}", err) })
+    }
+
+    /// Returns the address of the peer.
+    pub fn address_of(&self, id: &str) -> Result<Option<String>, String> {
+        self.peers.read().map(|cache| {
+            cache.peers.get(id).map(|val| val.address.clone())
+        }).map_err(|err| { error!("Poison error: {:?}", err); format!("Poison error: {:?}", err) })
+    }
+
+    /// Returns addresses of up to `count` random available peers, sorted by address.
+    /// Current node and peers with `exclude` ids are never chosen.
+    pub fn choose_available(&self, count: usize, exclude: &[&str]) -> Result<Vec<String>, String> {
+        self.peers.read().map(|cache| {
+            let candidates: Vec<&String> = cache.peers.values()
+                .filter(|val| val.available && cache.self_id.as_ref() != Some(&val.id)
+                    && !exclude.contains(&val.id.as_str()))
+                .map(|val| &val.address)
+                .collect();
+            let mut chosen: Vec<String> = candidates.choose_multiple(&mut rand::thread_rng(), count)
+                .map(|val| val.to_string())
+                .collect();
+            chosen.sort();
+            chosen
+        }).map_err(|err| { error!("Poison error: {:?}", err); format!("Poison error: {:?}", err) })
+    }
+
 }
\ No newline at end of file
@@ -0,0 +1,70 @@
+use log::warn;
+
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::net::ToSocketAddrs;
+use std::sync::{Arc, RwLock};
+
+/// Turns a seed from `--connect` into addresses of the peers behind it.
+pub trait SeedResolver: Debug + Send + Sync {
+    fn resolve(&self, seed: &str) -> Result<Vec<String>, String>;
+}
+
+/// Resolves `host:port` seeds through the system resolver, so a DNS name with several
+/// A/AAAA records gives all of them with the same port. IP seeds are returned as is.
+#[derive(Debug, Clone, Default)]
+pub struct SystemResolver;
+
+impl SeedResolver for SystemResolver {
+    fn resolve(&self, seed: &str) -> Result<Vec<String>, String> {
+        seed.to_socket_addrs()
+            .map(|addrs| addrs.map(|addr| addr.to_string()).collect())
+            .map_err(|err| format!("Error on resolving `{}`: {:?}", seed, err))
+    }
+}
+
+/// Resolver with records set manually, unknown seeds aren't resolved. All clones share
+/// the same records, so they can be changed after the resolver has been passed to the node.
+#[derive(Debug, Clone, Default)]
+pub struct StaticResolver {
+    records: Arc<RwLock<HashMap<String, Vec<String>>>>
+}
+
+impl StaticResolver {
+    pub fn new() -> Self {
+        StaticResolver::default()
+    }
+
+    pub fn set(&self, seed: &str, addresses: &[&str]) {
+        if let Ok(mut records) = self.records.write() {
+            records.insert(seed.to_string(), addresses.iter().map(|val| val.to_string()).collect());
+        }
+    }
+}
+
+impl SeedResolver for StaticResolver {
+    fn resolve(&self, seed: &str) -> Result<Vec<String>, String> {
+        self.records.read()
+            .map_err(|err| format!("Poison error: {:?}", err))?
+            .get(seed)
+            .cloned()
+            .ok_or_else(|| format!("No records for `{}`", seed))
+    }
+}
+
+/// Resolves all seeds and returns their addresses without duplicates in the order of seeds.
+/// Seeds, which couldn't be resolved, are skipped.
+pub fn resolve_all(resolver: &dyn SeedResolver, seeds: &[String]) -> Vec<String> {
+    let mut addresses: Vec<String> = vec![];
+    for seed in seeds {
+        match resolver.resolve(seed) {
+            Ok(resolved) => for address in resolved {
+                if !addresses.contains(&address) {
+                    addresses.push(address);
+                }
+            },
+            Err(err) => warn!("{}", err)
+        };
+    }
+    addresses
+}
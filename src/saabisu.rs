@@ -1,211 +1,546 @@
 use crate::p2pcache::{PeerCache, PeerList};
+use crate::merkle::{Digest, SyncRequest};
+use crate::node::{Message, Outbox};
+use crate::selector::PeerSelector;
+use crate::stats::Stats;
+use crate::backoff::Backoff;
+use crate::client::{TlsConfig, PROBE_TIMEOUT};
+use crate::resolver::{resolve_all, SeedResolver, SystemResolver};
+use crate::server::OBSERVED_HEADER;
 
 use reqwest;
 use log::{error, info, trace};
 use reqwest::StatusCode;
-use rand::{distributions::Alphanumeric, Rng};
-use serde_json::from_str as js_from_str;
-use native_tls;
+use rand::seq::SliceRandom;
+use tokio::task::JoinSet;
+use tokio::time::{interval_at, sleep, Instant, MissedTickBehavior};
 
+use std::collections::HashMap;
 use std::sync::{Arc, Condvar, Mutex};
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::thread;
 use std::time;
 
+/// First delay between failed attempts to join through the seeds.
+const JOIN_BACKOFF_MIN: time::Duration = time::Duration::from_secs(1);
+/// Maximal delay between failed attempts to join through the seeds.
+const JOIN_BACKOFF_MAX: time::Duration = time::Duration::from_secs(60);
+
 pub type SignalT = Arc<(Mutex<bool>, Condvar)>;
 
-fn random_msg() -> String {
-    rand::thread_rng()
-        .sample_iter(&Alphanumeric)
-        .take(16)
-        .map(char::from)
-        .collect()
+/// Number of unavailable peers pinged every round besides the available ones, so peers,
+/// which have come back, are noticed before they are removed.
+const DEAD_PROBES: usize = 1;
+
+// Asks up to `indirect_probes` random helpers to ping the peer `id` on our behalf, all at once.
+// Returns true as soon as any of the helpers has reached the peer.
+async fn indirect_probe(client: &reqwest::Client, name: &str, id: &str, helpers: &[String], indirect_probes: u32) -> bool {
+    let chosen: Vec<String> = helpers.choose_multiple(&mut rand::thread_rng(), indirect_probes as usize).cloned().collect();
+    trace!("Probing `{}` indirectly through {:?}", id, chosen);
+    let mut probes = JoinSet::new();
+    for helper in chosen {
+        let request = client.get(format!("https://{}/ping-req", helper)).query(&[("peer_name", name), ("id", id)]);
+        probes.spawn(async move {
+            request.send()
+                .await
+                .map(|val| val.status() == StatusCode::OK)
+                .unwrap_or_else(|err| {
+                    info!("Couldn't request indirect probe from peer {}: {:?}", helper, err);
+                    false
+                })
+        });
+    }
+    while let Some(result) = probes.join_next().await {
+        if result.map_err(|err| {error!("Error on joining the task: {:?}", err)}).unwrap_or(false) {
+            return true;
+        }
+    }
+    false
+}
+
+// Sends the request and returns true if the peer has replied with OK.
+async fn is_reached(peer: &str, request: reqwest::RequestBuilder) -> bool {
+    request.send()
+        .await
+        .map(|val| {
+            trace!("Message sent response: {:?}", val);
+            val.status() == StatusCode::OK
+        })
+        .unwrap_or_else(|err| {
+            info!("Couldn't send message to peer {}: {:?}", peer, err);
+            false
+        })
+}
+
+// Sends the message to the given peers, every peer in its own task.
+fn disseminate(client: &reqwest::Client, message: &Message, peers: Vec<String>, tasks: &mut JoinSet<bool>) {
+    for peer in peers {
+        let request = client.get(format!("https://{}/message", peer)).json(message);
+        tasks.spawn(async move {
+            is_reached(&peer, request).await;
+            false
+        });
+    }
 }
 
-// Sends a message to all other peers.
-fn messenger(name: &str, cache: &mut PeerCache) -> Result<bool, String> {
-    let changed = Arc::new(AtomicBool::new(false));
-    let mut handles = vec![];
-    let all_peers: Vec<String> = cache.get_list()?.peers
+// Sends queued messages to `fanout` random peers and pings `fanout` available peers chosen
+// by the `selector`. Peers, that couldn't be reached neither directly nor through `indirect_probes`
+// other peers are marked as suspects, and suspects, which haven't refuted suspicion
+// for `suspect_timeout` seconds, are marked as unavailable. Unavailable peers don't take the places
+// of the available ones, `DEAD_PROBES` random ones of them are pinged directly, and the ones,
+// which have answered, are marked as available again.
+async fn messenger(client: &reqwest::Client, name: &str, cache: &mut PeerCache, outbox: &Outbox, config: &SaabisuConfig,
+                   selector: &mut PeerSelector, stats: &Arc<Stats>) -> Result<bool, String> {
+    let mut tasks = JoinSet::new();
+    let peer_list = cache.get_list()?.peers;
+    let addresses: HashMap<String, String> = peer_list
         .iter()
-        .filter(|x| x.address != name)
-        .map(|x| x.address.clone())
+        .filter(|x| x.id != name && !x.left)
+        .map(|x| (x.id.clone(), x.address.clone()))
         .collect();
-    let message = random_msg();
-    info!("Sending message `{}` to {:?}", message, all_peers);
-    for peer in all_peers {
-        let changed_copy = changed.clone();
-        let changed_copy_or = changed.clone();
+    let available_ids: Vec<String> = peer_list
+        .iter()
+        .filter(|x| addresses.contains_key(&x.id) && x.available)
+        .map(|x| x.id.clone())
+        .collect();
+    let dead_ids: Vec<String> = peer_list
+        .iter()
+        .filter(|x| addresses.contains_key(&x.id) && !x.available)
+        .map(|x| x.id.clone())
+        .collect();
+    let available_peers: Vec<String> = available_ids.iter().map(|id| addresses[id].clone()).collect();
+    for message in outbox.drain()? {
+        let chosen = cache.choose_available(config.fanout, &[name])?;
+        info!("Sending message `{}` to {:?}", String::from_utf8_lossy(&message.payload), chosen);
+        stats.messages_sent(chosen.len() as u64);
+        disseminate(client, &message, chosen, &mut tasks);
+    }
+    let indirect_probes = config.indirect_probes;
+    for id in selector.choose(&available_ids) {
+        let address = addresses[&id].clone();
         let mut cache_copy = cache.clone();
-        let mut cache_copy_or = cache.clone();
-        let peer_copy = peer.clone();
-        let peer_copy_or = peer.clone();
+        let client_copy = client.clone();
         let name_copy = name.to_string();
-        let message_copy = message.clone();
-        handles.push(thread::spawn( move || {
-            let tls = native_tls::TlsConnector::builder()
-                .use_sni(false)
-                .danger_accept_invalid_certs(true)
-                .build()
-                .unwrap();
-            let client = reqwest::blocking::ClientBuilder::new()
-                //.use_rustls_tls()
-                .use_preconfigured_tls(tls)
-                .timeout(time::Duration::new(10, 0))
-                .build()
-                .map_err(|err| {
-                    error!("Error on building the client: {:?}", err);
-                }).unwrap();
-            client.get(format!("https://{}/message", peer_copy))
-                .query(&[("peer_name", name_copy), ("msg", message_copy)])
-                .send()
-                .map(move |val| {
-                    trace!("Message sent response: {:?}", val);
-                    changed_copy.fetch_or(cache_copy.update_peer(&peer_copy, val.status() == StatusCode::OK).map_or(false, |x| x), Ordering::SeqCst);
-                })
-                .map_err(|err| {
-                    info!("Couldn't send message to peer {}: {:?}", peer_copy_or, err);
-                    changed_copy_or.fetch_or(cache_copy_or.update_peer(&peer_copy_or, false).unwrap_or(false), Ordering::SeqCst);
-                }).unwrap_or(());
-        }));
+        let stats_copy = stats.clone();
+        let helpers: Vec<String> = available_peers.iter().filter(|x| **x != address).cloned().collect();
+        tasks.spawn(async move {
+            let request = client_copy.get(format!("https://{}/ping", address)).timeout(PROBE_TIMEOUT).query(&[("id", &id)]);
+            let reached = is_reached(&address, request).await
+                || indirect_probe(&client_copy, &name_copy, &id, &helpers, indirect_probes).await;
+            stats_copy.probed(reached);
+            let updated = if reached {
+                cache_copy.update_peer(&id, &address, true)
+            } else {
+                info!("Peer {} at {} is suspected to be failed", id, address);
+                cache_copy.suspect_peer(&id)
+            };
+            updated.unwrap_or(false)
+        });
+    }
+    for id in dead_ids.choose_multiple(&mut rand::thread_rng(), DEAD_PROBES) {
+        let (id, address) = (id.clone(), addresses[id].clone());
+        let mut cache_copy = cache.clone();
+        let request = client.get(format!("https://{}/ping", address)).timeout(PROBE_TIMEOUT).query(&[("id", &id)]);
+        tasks.spawn(async move {
+            if !is_reached(&address, request).await {
+                return false;
+            }
+            info!("Peer {} at {} is available again", id, address);
+            cache_copy.update_peer(&id, &address, true).unwrap_or(false)
+        });
     }
-    for thr in handles {
-        thr.join().map_err(|err| {error!("Error on joning the thread: {:?}", err)}).unwrap_or(());
+    let mut changed = false;
+    while let Some(result) = tasks.join_next().await {
+        changed |= result.map_err(|err| {error!("Error on joining the task: {:?}", err)}).unwrap_or(false);
     }
-    Ok(changed.load(Ordering::SeqCst))
+    changed |= cache.expire_suspects(config.suspect_timeout)?;
+    Ok(changed)
 }
 
-// Sends current PeerList to all other peers.
-fn updater(name: &str, cache: &mut PeerCache) -> Result<(), String> {
-    let mut handles = vec![];
-    let all_peers: Vec<String> = cache.get_list()?.peers
+// Sends changes of the PeerList to `fanout` outdated available peers chosen by the `selector`.
+// Every peer gets only entries changed since the last successful update sent to it,
+// which is tracked in `watermarks`. Peers without watermark (new ones, unavailable ones
+// or ones, which failed to receive the last update) get the full list.
+// Returns true if some peers are still outdated and should be updated on the next round.
+async fn updater(client: &reqwest::Client, name: &str, cache: &mut PeerCache, watermarks: &mut HashMap<String, u64>,
+                 selector: &mut PeerSelector, stats: &Stats) -> Result<bool, String> {
+    let mut tasks = JoinSet::new();
+    let peer_list = cache.get_list()?.peers;
+    let addresses: HashMap<String, String> = peer_list
         .iter()
-        .filter(|x| x.address != name)
-        .map(|x| x.address.clone())
+        .filter(|x| x.id != name && x.available)
+        .map(|x| (x.id.clone(), x.address.clone()))
+        .collect();
+    watermarks.retain(|peer, _| addresses.contains_key(peer));
+    trace!("PeerList: {:?}", &peer_list);
+    let current = cache.version()?;
+    let outdated: Vec<String> = addresses.keys()
+        .filter(|peer| watermarks.get(*peer).is_none_or(|watermark| *watermark < current))
+        .cloned()
         .collect();
-    trace!("PeerList: {:?}", &cache.get_list().unwrap_or(PeerList{peers: vec![]}));
-    for peer in all_peers {
-        let cache_copy = cache.clone();
-        let peer_copy = peer.clone();
-        let peer_copy_or = peer.clone();
-        handles.push(thread::spawn(move || {
-            let tls = native_tls::TlsConnector::builder()
-                .use_sni(false)
-                .danger_accept_invalid_certs(true)
-                .build()
-                .unwrap();
-            let client = reqwest::blocking::ClientBuilder::new()
-                //.use_rustls_tls()
-                .use_preconfigured_tls(tls)
-                .timeout(time::Duration::new(10, 0))
-                .build()
-                .map_err(|err| {
-                    error!("Error on building the client: {:?}", err);
-                }).unwrap();
-            client.get(format!("https://{}/update", peer_copy))
-                .json(&cache_copy.get_list().unwrap_or(PeerList{peers: vec![]}))
-                .send()
-                .map(move |val| {
+    let chosen = selector.choose(&outdated);
+    let pending = outdated.len() > chosen.len();
+    for peer in chosen {
+        let watermark = watermarks.remove(&peer).unwrap_or(0);
+        let (delta, version) = cache.get_delta(watermark)?;
+        if delta.peers.is_empty() {
+            watermarks.insert(peer, version);
+            continue;
+        }
+        let address = &addresses[&peer];
+        trace!("Sending {} entries to {} at {} since version {}", delta.peers.len(), peer, address, watermark);
+        let request = client.get(format!("https://{}/update", address)).json(&delta);
+        tasks.spawn(async move {
+            request.send()
+                .await
+                .map_err(|err: reqwest::Error| {
+                    info!("Couldn't send update to peer {}: {:?}", peer, err);
+                })
+                .ok()
+                .filter(|val| {
                     trace!("Update sent response: {:?}", val);
-                    ()
+                    val.status() == StatusCode::OK
                 })
-                .map_err(|err: reqwest::Error| {
-                    info!("Couldn't send update to peer {}: {:?}", peer_copy_or, err);
-                }).unwrap_or(());
-
-        }));
+                .map(|_| (peer, version))
+        });
     }
-    for thr in handles {
-        thr.join().map_err(|err| {error!("Error on joning the thread: {:?}", err)}).unwrap_or(());
+    while let Some(result) = tasks.join_next().await {
+        if let Some((peer, version)) = result.map_err(|err| {error!("Error on joining the task: {:?}", err)}).unwrap_or(None) {
+            watermarks.insert(peer, version);
+            stats.updates_sent(1);
+        }
     }
-    Ok(())
+    Ok(pending)
 }
-// Retrieves initial PeerList from another peer.
-fn connect_to_first_peer(self_name: &str, cache: &mut PeerCache, address: &str) -> Result<(), String> {
-    let self_name_copy = self_name.to_string();
-    let tls = native_tls::TlsConnector::builder()
-        .use_sni(false)
-        .danger_accept_invalid_certs(true)
-        .build()
-        .unwrap();
-    let client = reqwest::blocking::ClientBuilder::new()
-        .use_preconfigured_tls(tls)// TODO delete on prod
-        .timeout(time::Duration::new(10, 0))
-        .build()
-        .map_err(|err| {
-            error!("Error on building the client: {:?}", err);
-            format!("Error on building the client: {:?}", err)
-        })?;
-    client.get(format!("https://{}/peers/{}", address, self_name_copy))
-        .body(self_name_copy)
+
+// Sends own entry to the peer and returns its reply. If the peer has told the address we're seen from,
+// and own address is unspecified, adopts it.
+async fn introduce(client: &reqwest::Client, cache: &mut PeerCache, address: &str) -> Result<(reqwest::Response, bool), String> {
+    let own = cache.self_state()?.ok_or_else(|| "Own entry isn't set".to_string())?;
+    let val = client.get(format!("https://{}/peers", address))
+        .json(&own)
         .send()
-        .map_or_else(|err| {
+        .await
+        .map_err(|err| {
             info!("Couldn't request peers from `{}`: {:?}", address, err);
-            Err("Couldn't connect".to_string())
-        }, |val: reqwest::blocking::Response| {
-            trace!("{:?}", val);
-            if val.status() == StatusCode::OK {
-                cache.update_from_list(
-                    &js_from_str(
-                        match val.text() {
-                            Ok(val) => val,
-                            Err(err) => return Err(format!("{:?}", err))
-                        }.as_str()
-                        // .await
-                    ).map_err(|err: serde_json::Error| -> String { format!("{:?}", err) })?)
-                .map_err(|err| { format!("{:?}", err)})?;
-            }
-            Ok(())
-        })
+            "Couldn't connect".to_string()
+        })?;
+    trace!("{:?}", val);
+    let discovered = match val.headers().get(OBSERVED_HEADER).and_then(|observed| observed.to_str().ok()) {
+        Some(observed) => cache.adopt_observed(observed)?,
+        None => false
+    };
+    Ok((val, discovered))
+}
+
+// Introduces the node to another peer with its own entry and retrieves initial PeerList from it.
+// If the node has discovered its address from the reply, introduces itself again with it,
+// since the peer may have rejected the unspecified one.
+async fn connect_to_first_peer(client: &reqwest::Client, cache: &mut PeerCache, address: &str) -> Result<(), String> {
+    let (mut val, discovered) = introduce(client, cache, address).await?;
+    if discovered {
+        val = introduce(client, cache, address).await?.0;
+    }
+    if val.status() != StatusCode::OK {
+        return Err(format!("Peer has replied with {}", val.status()));
+    }
+    let list: PeerList = val.json().await.map_err(|err| format!("{:?}", err))?;
+    cache.update_from_list(&list).map_err(|err| { format!("{:?}", err)})?;
+    Ok(())
+}
+
+// Returns true if the node knows some available peer besides itself.
+fn has_peers(cache: &PeerCache, self_id: &str) -> Result<bool, String> {
+    Ok(!cache.choose_available(1, &[self_id])?.is_empty())
+}
+
+// Tries seeds one by one until the node learns some available peer from one of them.
+// Returns true if it has joined the cluster.
+async fn join(client: &reqwest::Client, self_id: &str, cache: &mut PeerCache, seeds: &[String]) -> Result<bool, String> {
+    for seed in seeds {
+        match connect_to_first_peer(client, cache, seed).await {
+            Ok(_) if has_peers(cache, self_id)? => {
+                info!("Connected to `{}`", seed);
+                // introduce own signed entry to the peers we've just learned about
+                cache.signaler.broadcast().map_err(|_| {error!("Error on broadcasting");}).unwrap_or(());
+                return Ok(true);
+            },
+            Ok(_) => info!("Seed `{}` doesn't know any other peers", seed),
+            Err(err) => error!("Error on connecting to `{}`: `{}`", seed, err)
+        };
+    }
+    Ok(false)
+}
+
+// Resolves the seeds into addresses of the peers without blocking the runtime.
+async fn resolve_seeds(resolver: &Arc<dyn SeedResolver>, seeds: &[String]) -> Vec<String> {
+    let (resolver, seeds_copy) = (resolver.clone(), seeds.to_vec());
+    let addresses = tokio::task::spawn_blocking(move || resolve_all(resolver.as_ref(), &seeds_copy))
+        .await
+        .unwrap_or_default();
+    trace!("Seeds {:?} are resolved into {:?}", seeds, addresses);
+    addresses
+}
+
+// Introduces the node to the seed addresses, which have appeared since the previous resolution,
+// so the node learns about peers, which have been added to the DNS records after it has joined.
+async fn introduce_new_seeds(client: &reqwest::Client, cache: &mut PeerCache, addresses: &[String], known: &[String]) {
+    for address in addresses.iter().filter(|address| !known.contains(address)) {
+        match connect_to_first_peer(client, cache, address).await {
+            Ok(_) => {
+                info!("Connected to new seed `{}`", address);
+                cache.signaler.broadcast().map_err(|_| {error!("Error on broadcasting");}).unwrap_or(());
+            },
+            Err(err) => info!("Error on connecting to new seed `{}`: `{}`", address, err)
+        };
+    }
+}
+
+// Compares Merkle digest with one random available peer and exchanges entries
+// of the differing buckets. Returns true if the local list has changed.
+async fn anti_entropy(client: &reqwest::Client, name: &str, cache: &mut PeerCache, stats: &Stats) -> Result<bool, String> {
+    let peer = match cache.get_list()?.peers
+        .iter()
+        .filter(|x| x.id != name && x.available)
+        .map(|x| x.address.clone())
+        .collect::<Vec<String>>()
+        .choose(&mut rand::thread_rng()) {
+        Some(val) => val.clone(),
+        None => return Ok(false)
+    };
+    let remote: Digest = match client.get(format!("https://{}/digest", peer)).send().await {
+        Ok(val) => val.json().await,
+        Err(err) => Err(err)
+    }.map_err(|err| format!("Couldn't get digest from `{}`: {:?}", peer, err))?;
+    let buckets = cache.digest()?.diff(&remote);
+    if buckets.is_empty() {
+        trace!("PeerList is in sync with `{}`", peer);
+        return Ok(false);
+    }
+    trace!("Syncing buckets {:?} with `{}`", buckets, peer);
+    let request = SyncRequest { peers: cache.get_buckets(&buckets)?, buckets };
+    let reply: PeerList = match client.get(format!("https://{}/sync", peer)).json(&request).send().await {
+        Ok(val) => val.json().await,
+        Err(err) => Err(err)
+    }.map_err(|err| format!("Couldn't sync with `{}`: {:?}", peer, err))?;
+    stats.synced();
+    cache.update_from_list(&reply)
+}
+
+/// Marks current node as left and sends its entry to `fanout` random available peers, so they don't wait
+/// for it to fail probes. They spread it further with updates and anti-entropy, so the load doesn't
+/// depend on the size of the cluster. Returns the number of peers, which have received it.
+pub async fn announce_leave(client: &reqwest::Client, cache: &mut PeerCache, fanout: usize) -> Result<usize, String> {
+    let own = match cache.leave()? {
+        Some(val) => val,
+        None => return Ok(0)
+    };
+    let list = PeerList { peers: vec![own.clone()] };
+    let mut tasks = JoinSet::new();
+    for peer in cache.choose_available(fanout, &[&own.id])? {
+        let request = client.get(format!("https://{}/update", peer)).json(&list);
+        tasks.spawn(async move {
+            request.send()
+                .await
+                .map_err(|err| info!("Couldn't announce leaving to peer {}: {:?}", peer, err))
+                .is_ok_and(|val| val.status() == StatusCode::OK)
+        });
+    }
+    let mut announced = 0;
+    while let Some(result) = tasks.join_next().await {
+        if result.map_err(|err| {error!("Error on joining the task: {:?}", err)}).unwrap_or(false) {
+            announced += 1;
+        }
+    }
+    Ok(announced)
+}
+
+/// Options of the gossiping services.
+#[derive(Serialize, Debug, Clone)]
+pub struct SaabisuConfig {
+    /// address:port of the seed peers to retrieve PeerList from, DNS names give all their addresses
+    pub connect: Vec<String>,
+    /// Resolves seeds into addresses of the peers before every attempt to join
+    #[serde(skip)]
+    pub resolver: Arc<dyn SeedResolver>,
+    /// Period of resolving the seeds again while the node is joined in seconds, 0 disables it
+    pub resolve_period: u32,
+    /// Period of sending messages in seconds
+    pub period: u32,
+    /// Timeout for peer connection in seconds
+    pub timeout: u32,
+    /// Number of peers asked to probe unreachable peer
+    pub indirect_probes: u32,
+    /// Seconds after which suspected peer is considered unavailable
+    pub suspect_timeout: u32,
+    /// Period of anti-entropy rounds in seconds, 0 disables them
+    pub sync_period: u32,
+    /// Number of peers every message is sent or forwarded to
+    pub fanout: usize,
+    /// Number of hops every message can be forwarded
+    pub ttl: u32,
+    /// Number of peers pinged and updated every round
+    pub gossip_fanout: usize,
+    /// Prefer peers, which haven't been pinged or updated for the longest time, instead of random ones
+    pub prefer_stale: bool,
+    /// Certificate of the node and verification of other peers
+    pub tls: TlsConfig,
+}
+
+impl Default for SaabisuConfig {
+    /// Same options as the defaults of the command line, without seeds.
+    fn default() -> Self {
+        SaabisuConfig {
+            connect: vec![],
+            resolver: Arc::new(SystemResolver),
+            resolve_period: 60,
+            period: 1,
+            timeout: 30,
+            indirect_probes: 3,
+            suspect_timeout: 10,
+            sync_period: 10,
+            fanout: 3,
+            ttl: 4,
+            gossip_fanout: 3,
+            prefer_stale: false,
+            tls: TlsConfig::default(),
+        }
+    }
 }
 
 /// Run services:
-///  * retrieve first PeerList of *connect* is available
-///  * send random message every *period* to all other peers
-///  * keep track on updates and send them to all other peers
+///  * retrieve first PeerList from one of *connect* seeds resolved by *resolver*, retrying
+///    with exponential backoff, and rejoin through them whenever no other peers are available
+///  * resolve the seeds again every *resolve_period* and introduce the node to the new addresses
+///  * send queued messages every *period* to *fanout* random peers
+///  * ping *gossip_fanout* peers every *period*, probe unreachable ones through *indirect_probes*
+///    other peers and declare suspects unavailable after *suspect_timeout*
+///  * keep track on updates and send changed entries to *gossip_fanout* peers
+///  * compare digests with random peer every *sync_period* seconds and exchange differing entries
 ///  * clean up old peers every timeout/2 seconds
-pub fn run_saabisu(self_name: &str, connect: &Option<String>, period: u32, timeout: u32, cache: &PeerCache) {
+///
+/// All requests are sent through the shared `client`, so connections to peers are reused between rounds.
+/// Activity of the services is counted in `stats`.
+///
+/// All services are spawned as tasks, so it must be called within tokio runtime.
+/// Returns the set of the tasks, which stops the services when shut down or dropped.
+pub fn run_saabisu(self_id: &str, config: &SaabisuConfig, cache: &PeerCache, outbox: &Outbox,
+                   client: &reqwest::Client, stats: &Arc<Stats>) -> JoinSet<()> {
+    let mut services = JoinSet::new();
+    let client_copy = client.clone();
+    let client_msg = client.clone();
+    let client_upd = client.clone();
+    let client_sync = client.clone();
     let mut cache_copy = cache.clone();
     let mut cache_copy_msg = cache.clone();
     let mut cache_copy_upd = cache.clone();
+    let mut cache_copy_sync = cache.clone();
     let mut cache_copy_clear = cache.clone();
-    let name_copy = self_name.to_string();
-    let name_copy_msg = self_name.to_string();
-    let name_copy_upd = self_name.to_string();
-
-    connect.clone().map(|first_peer| {
-        thread::spawn(move || {
-            match connect_to_first_peer(&name_copy, &mut cache_copy, &first_peer) {
-                Ok(_) => info!("Connected to `{}`", first_peer),
-                Err(err) => error!("Error on connecting to `{}`: `{}`", first_peer, err)
-            };
+    let name_copy = self_id.to_string();
+    let name_copy_msg = self_id.to_string();
+    let name_copy_upd = self_id.to_string();
+    let name_copy_sync = self_id.to_string();
+    let outbox_copy = outbox.clone();
+    let stats_msg = stats.clone();
+    let stats_upd = stats.clone();
+    let stats_sync = stats.clone();
+    let mut watermarks = HashMap::new();
+    // own subscription, so other tasks waiting for the signaler don't take updates of the updater
+    let mut updates = cache.signaler.subscribe();
+    let config_msg = config.clone();
+    let SaabisuConfig { connect, resolver, resolve_period, period, timeout, sync_period, gossip_fanout, prefer_stale, .. } = config.clone();
+    let mut selector_msg = PeerSelector::new(gossip_fanout, prefer_stale);
+    let mut selector_upd = PeerSelector::new(gossip_fanout, prefer_stale);
+    let round = time::Duration::new(period as u64, 0);
+
+    if !connect.is_empty() {
+        services.spawn(async move {
+            let mut backoff = Backoff::new(JOIN_BACKOFF_MIN, JOIN_BACKOFF_MAX);
+            let resolve_every = time::Duration::new(resolve_period as u64, 0);
+            let mut seeds: Vec<String> = vec![];
+            let mut resolved_at = Instant::now();
+            loop {
+                let joined = match has_peers(&cache_copy, &name_copy) {
+                    Ok(true) => {
+                        if resolve_period > 0 && resolved_at.elapsed() >= resolve_every {
+                            let addresses = resolve_seeds(&resolver, &connect).await;
+                            introduce_new_seeds(&client_copy, &mut cache_copy, &addresses, &seeds).await;
+                            seeds = addresses;
+                            resolved_at = Instant::now();
+                        }
+                        true
+                    },
+                    Ok(false) => {
+                        // seeds are resolved on every attempt, so changes of DNS records are picked up
+                        let addresses = resolve_seeds(&resolver, &connect).await;
+                        seeds = addresses.clone();
+                        resolved_at = Instant::now();
+                        join(&client_copy, &name_copy, &mut cache_copy, &addresses).await.unwrap_or_else(|err| {
+                            error!("Error on joining: {}", err);
+                            false
+                        })
+                    },
+                    Err(err) => {
+                        error!("Error on checking peers: {}", err);
+                        false
+                    }
+                };
+                if joined {
+                    backoff.reset();
+                    sleep(round).await;
+                } else {
+                    let delay = backoff.next_delay();
+                    info!("Couldn't join through {:?}, retrying in {:?}", connect, delay);
+                    sleep(delay).await;
+                }
+            }
         });
-    });
-    thread::spawn(move || {
+    }
+    services.spawn(async move {
+        // rounds are scheduled from the fixed start, so time spent on sending doesn't shift them
+        let mut rounds = interval_at(Instant::now() + round, round);
+        rounds.set_missed_tick_behavior(MissedTickBehavior::Skip);
         loop {
-            thread::sleep(time::Duration::new(period as u64, 0));
+            rounds.tick().await;
             trace!("Sending messages");
-            match messenger(&name_copy_msg, &mut cache_copy_msg) {
+            match messenger(&client_msg, &name_copy_msg, &mut cache_copy_msg, &outbox_copy, &config_msg, &mut selector_msg, &stats_msg).await {
                 Ok(updated) => if updated { cache_copy_msg.signaler.broadcast().map_err(|_| {error!("Error on broadcasting");}).unwrap_or(()); },
                 Err(err) => error!("Error on sending messages: {:?}", err)
             };
         }
     });
-    thread::spawn(move || {
+    services.spawn(async move {
+        let mut pending = false;
         loop {
-            cache_copy_upd.signaler.wait().map_err(|_| {error!("Error on waiting");}).unwrap_or(());
+            if pending {
+                // the rest of outdated peers is updated on the next rounds
+                sleep(round).await;
+            } else {
+                updates.wait().await;
+            }
             trace!("Replaying updates");
-            updater(&name_copy_upd, &mut cache_copy_upd).map_err(|err| {
+            pending = updater(&client_upd, &name_copy_upd, &mut cache_copy_upd, &mut watermarks, &mut selector_upd, &stats_upd).await.unwrap_or_else(|err| {
                 error!("Error on sending updates: {}", err);
-            }).unwrap_or(());
+                false
+            });
         }
     });
-    thread::spawn(move || {
+    if sync_period > 0 {
+        services.spawn(async move {
+            loop {
+                sleep(time::Duration::new(sync_period as u64, 0)).await;
+                match anti_entropy(&client_sync, &name_copy_sync, &mut cache_copy_sync, &stats_sync).await {
+                    Ok(updated) => if updated { cache_copy_sync.signaler.broadcast().map_err(|_| {error!("Error on broadcasting");}).unwrap_or(()); },
+                    Err(err) => info!("Error on anti-entropy round: {}", err)
+                };
+            }
+        });
+    }
+    services.spawn(async move {
        loop {
-           thread::sleep(time::Duration::new((timeout as u64) / 2, 0));
+           sleep(time::Duration::new((timeout as u64) / 2, 0)).await;
            cache_copy_clear.cleanup_old_peers().map_err(|err| {
                error!("Error on sending updates: {}", err);
            }).unwrap_or(());
        }
     });
-}
\ No newline at end of file
+    services
+}
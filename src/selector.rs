@@ -0,0 +1,38 @@
+use rand::seq::SliceRandom;
+
+use std::collections::HashMap;
+
+/// Chooses up to `fanout` peers for every gossip round, so the load of the node
+/// doesn't depend on the size of the cluster. With `prefer_stale` peers, which haven't
+/// been chosen for the longest time, go first, so every peer is contacted at least
+/// once in `peers / fanout` rounds; otherwise peers are chosen at random.
+#[derive(Debug, Clone, Default)]
+pub struct PeerSelector {
+    fanout: usize,
+    prefer_stale: bool,
+    round: u64,
+    contacted: HashMap<String, u64>
+}
+
+impl PeerSelector {
+    pub fn new(fanout: usize, prefer_stale: bool) -> Self {
+        PeerSelector { fanout, prefer_stale, ..Default::default() }
+    }
+
+    /// Chooses peers among `candidates` and remembers them as contacted in the current round.
+    pub fn choose(&mut self, candidates: &[String]) -> Vec<String> {
+        let mut chosen = candidates.to_vec();
+        chosen.shuffle(&mut rand::thread_rng());
+        if self.prefer_stale {
+            // never contacted peers have no round and go first, ties stay shuffled
+            chosen.sort_by_key(|peer| self.contacted.get(peer).copied());
+        }
+        chosen.truncate(self.fanout);
+        self.round += 1;
+        self.contacted.retain(|peer, _| candidates.contains(peer));
+        for peer in &chosen {
+            self.contacted.insert(peer.clone(), self.round);
+        }
+        chosen
+    }
+}
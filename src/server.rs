@@ -1,24 +1,168 @@
-use crate::p2pcache::{PeerCache, PeerList};
+use crate::p2pcache::{PeerCache, PeerList, PeerQuery, PeerState};
+use crate::merkle::{Digest, SyncRequest};
+use crate::node::{Inbox, Message};
+use crate::client::{TlsConfig, Verification, PROBE_TIMEOUT};
+use crate::identity::PeerIdentity;
+use crate::stats::Stats;
 
 use warp::{http::{StatusCode, Response}, Filter};
 use serde_json::to_string as js_to_string;
 use log::{error, info, trace, warn};
+use hyper::{server::conn::Http, service::service_fn};
+use tokio::net::TcpListener;
+use tokio_rustls::{rustls, TlsAcceptor};
+use tower_service::Service;
 
+use std::fs::File;
+use std::io::BufReader;
 use std::net::SocketAddr;
 use std::collections::HashMap;
+use std::sync::Arc;
 
-#[tokio::main]
-pub async fn run_server(bind: &str, port: u16, cert: &str, key: &str, cache: &PeerCache) {
+/// Header of the `/peers` reply with the address:port the joining peer is seen from.
+pub const OBSERVED_HEADER: &str = "x-observed-address";
+
+// Pings the `target` with the given `id` and returns OK if it has answered, SERVICE_UNAVAILABLE otherwise.
+async fn probe(client: &reqwest::Client, target: &str, id: &str) -> StatusCode {
+    let request = client.get(format!("https://{}/ping", target)).timeout(PROBE_TIMEOUT).query(&[("id", id)]);
+    match request.send().await {
+        Ok(val) if val.status() == StatusCode::OK => StatusCode::OK,
+        Ok(val) => {
+            info!("Indirect probe of `{}` failed with {}", target, val.status());
+            StatusCode::SERVICE_UNAVAILABLE
+        },
+        Err(err) => {
+            info!("Indirect probe of `{}` failed: {:?}", target, err);
+            StatusCode::SERVICE_UNAVAILABLE
+        }
+    }
+}
+
+// Forwards the message with decreased ttl to `fanout` random peers except the origin.
+fn forward(client: &reqwest::Client, message: &Message, cache: &PeerCache, fanout: usize, stats: &Stats) -> Result<(), String> {
+    let peers = cache.choose_available(fanout, &[&message.origin])?;
+    trace!("Forwarding message `{}` to {:?}", message.id, peers);
+    stats.messages_forwarded(peers.len() as u64);
+    let forwarded = Message { ttl: message.ttl - 1, ..message.clone() };
+    for peer in peers {
+        let request = client.get(format!("https://{}/message", peer)).json(&forwarded);
+        tokio::spawn(async move {
+            if let Err(err) = request.send().await {
+                info!("Couldn't forward message to peer {}: {:?}", peer, err);
+            }
+        });
+    }
+    Ok(())
+}
+
+// Reads all certificates from the PEM file.
+fn load_certs(path: &str) -> Result<Vec<rustls::pki_types::CertificateDer<'static>>, String> {
+    let file = File::open(path).map_err(|err| format!("Error on opening `{}`: {:?}", path, err))?;
+    rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| format!("Error on reading certificates from `{}`: {:?}", path, err))
+}
+
+// Builds TLS config of the server. With the cluster CA clients must present certificates issued by it.
+fn build_tls(tls: &TlsConfig) -> Result<rustls::ServerConfig, String> {
+    let certs = load_certs(&tls.cert)?;
+    let file = File::open(&tls.key).map_err(|err| format!("Error on opening `{}`: {:?}", tls.key, err))?;
+    let key = rustls_pemfile::private_key(&mut BufReader::new(file))
+        .map_err(|err| format!("Error on reading the key from `{}`: {:?}", tls.key, err))?
+        .ok_or_else(|| format!("No private key in `{}`", tls.key))?;
+    let builder = rustls::ServerConfig::builder();
+    let builder = match &tls.verification {
+        Verification::Ca(ca) => {
+            let mut roots = rustls::RootCertStore::empty();
+            for cert in load_certs(ca)? {
+                roots.add(cert).map_err(|err| format!("Error on adding the CA `{}`: {:?}", ca, err))?;
+            }
+            let verifier = rustls::server::WebPkiClientVerifier::builder(Arc::new(roots))
+                .build()
+                .map_err(|err| format!("Error on building the verifier: {:?}", err))?;
+            builder.with_client_cert_verifier(verifier)
+        },
+        _ => builder.with_no_client_auth()
+    };
+    let mut config = builder.with_single_cert(certs, key)
+        .map_err(|err| format!("Error on loading the certificate `{}`: {:?}", tls.cert, err))?;
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(config)
+}
+
+// Returns true if the message is signed with the key pinned for its origin
+// or if signatures aren't verified, because the node key isn't set.
+fn is_authentic(message: &Message, cache: &PeerCache) -> Result<bool, String> {
+    if cache.node_key().is_none() {
+        return Ok(true);
+    }
+    Ok(message.is_signed() && cache.key_of(&message.origin)?.is_some_and(|key| key == message.key))
+}
+
+/// Read-only service discovery: returns peers matching the query, e.g. `/peers?tag=role:db&available=true`.
+pub fn query_route(cache: &PeerCache)
+    -> impl Filter<Extract=(Result<Response<String>, warp::http::Error>,), Error=warp::Rejection> + Clone {
+    let cache = cache.clone();
+    warp::path("peers")
+        .and(warp::path::end())
+        .and(warp::query::<PeerQuery>())
+        .map(move |query: PeerQuery| {
+            cache.query(&query).and_then(|peers_l: PeerList| {
+                js_to_string(&peers_l).map_err(|err| format!("Error on jsoning the PeerList: {:?}", err))
+            }).map_or_else(|err| {
+                error!("Error on querying peers: {}", err);
+                Response::builder().status(StatusCode::INTERNAL_SERVER_ERROR).body("".to_string())
+            }, |v| { Response::builder().body(v) })
+        })
+}
+
+/// Options of the server.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// IP to bind the server on
+    pub bind: String,
+    /// Port to bind on
+    pub port: u16,
+}
+
+/// Serves the gossip API until the runtime is shut down. Messages are forwarded
+/// to `fanout` peers through the shared `client`. With the cluster CA in `tls`
+/// peers are authenticated by their certificates, which must contain the address
+/// they claim to have. Received messages and updates are counted in `stats`.
+/// Returns an error if the certificate or the key can't be loaded or the address can't be bound.
+pub async fn run_server(config: &ServerConfig, tls: &TlsConfig, cache: &PeerCache, inbox: &Inbox,
+                        client: &reqwest::Client, fanout: usize, stats: &Arc<Stats>) -> Result<(), String> {
+    let authenticate = tls.is_mutual();
     let cache_clone = cache.clone();
     let cache_clone_mut = cache.clone();
+    let cache_clone_digest = cache.clone();
+    let cache_clone_sync = cache.clone();
+    let inbox_clone = inbox.clone();
+    let cache_clone_msg = cache.clone();
+    let cache_clone_ping = cache.clone();
+    let cache_clone_probe = cache.clone();
+    let stats_upd = stats.clone();
+    let stats_msg = stats.clone();
+    let client_msg = client.clone();
+    let client_probe = client.clone();
 
-    // Receives a self-name of the peer and returns a list of peers. Also adds peer to the list.
+    // Receives an entry of the joining peer and returns a list of peers. Also adds peer to the list.
+    // Every reply tells the peer the address it's seen from, so it can discover its reachable address.
     let peers_srv = warp::path("peers")
         .and(warp::get())
-        .and(warp::path::param())
-        .map(move |back_name: String|  {
+        .and(warp::body::json::<PeerState>())
+        .and(warp::ext::optional::<PeerIdentity>())
+        .and(warp::ext::optional::<SocketAddr>())
+        .map(move |joining: PeerState, identity: Option<PeerIdentity>, remote: Option<SocketAddr>|  {
+            let observed = remote.map(|addr| addr.to_string()).unwrap_or_default();
+            trace!("Peer `{}` claims address `{}` and is seen from `{}`", joining.id, joining.address, observed);
+            if authenticate && !identity.as_ref().is_some_and(|identity| identity.matches(&joining.address)) {
+                warn!("Peer `{}` isn't authenticated by its certificate {:?}", joining.address, identity);
+                return warp::reply::with_header(
+                    Response::builder().status(StatusCode::FORBIDDEN).body("".to_string()), OBSERVED_HEADER, observed);
+            }
             let mut mut_cache = cache_clone.clone();
-            mut_cache.update_peer(&back_name, true).map_or_else(|err| {
+            let reply = mut_cache.update_from_list(&PeerList { peers: vec![joining] }).map_or_else(|err| {
                 error!("Error on updating the peer the PeerList: {}", err);
                 Response::builder().status(StatusCode::INTERNAL_SERVER_ERROR).body("".to_string())
             }, |val: bool| -> Result<Response<std::string::String>, warp::http::Error> {
@@ -34,14 +178,19 @@ pub async fn run_server(bind: &str, port: u16, cert: &str, key: &str, cache: &Pe
                     }, |v| { Response::builder().body(v) })
 
                 })
-            })
+            });
+            warp::reply::with_header(reply, OBSERVED_HEADER, observed)
         });
 
+    // Requests without the entry of the joining peer end up here.
+    let query_srv = query_route(cache);
+
     // Handle for receiving PeerLists from others. On update, broadcasts for waiters.
     let update_peers_srv = warp::get()
         .and(warp::path("update"))
         .and(warp::body::json::<PeerList>())
         .map(move |new_peers_list: PeerList| {
+            stats_upd.update_received();
             let updated = match cache_clone_mut.clone().update_from_list(&new_peers_list) {
                 Ok(val) => val,
                 Err(err) => {
@@ -53,27 +202,132 @@ pub async fn run_server(bind: &str, port: u16, cert: &str, key: &str, cache: &Pe
             Response::builder().status(StatusCode::OK).body("".to_string())
         });
 
-    // Handle for receiving the messages. Actually, doesn't update state of peers.
+    // Returns Merkle digest of the PeerList for anti-entropy rounds.
+    let digest_srv = warp::get()
+        .and(warp::path("digest"))
+        .map(move || {
+            cache_clone_digest.digest().and_then(|digest: Digest| {
+                js_to_string(&digest).map_err(|err| format!("Error on jsoning the digest: {:?}", err))
+            }).map_or_else(|err| {
+                error!("Error on getting the digest: {}", err);
+                Response::builder().status(StatusCode::INTERNAL_SERVER_ERROR).body("".to_string())
+            }, |v| { Response::builder().body(v) })
+        });
+
+    // Merges entries of the differing buckets and replies with own entries in them.
+    // On update, broadcasts for waiters.
+    let sync_srv = warp::get()
+        .and(warp::path("sync"))
+        .and(warp::body::json::<SyncRequest>())
+        .map(move |request: SyncRequest| {
+            let mut mut_cache = cache_clone_sync.clone();
+            let reply = mut_cache.get_buckets(&request.buckets);
+            match mut_cache.update_from_list(&request.peers) {
+                Ok(true) => mut_cache.signaler.broadcast().map_err(|_| {error!("Error on broadcasting");}).unwrap_or(()),
+                Ok(false) => (),
+                Err(err) => {
+                    error!("Error on updating the PeerList: {}", err);
+                    return Response::builder().status(StatusCode::INTERNAL_SERVER_ERROR).body("".to_string());
+                }
+            };
+            reply.and_then(|peers_l: PeerList| {
+                trace!("Sync reply: {:?}", peers_l);
+                js_to_string(&peers_l).map_err(|err| format!("Error on jsoning the PeerList: {:?}", err))
+            }).map_or_else(|err| {
+                error!("Error on syncing buckets: {}", err);
+                Response::builder().status(StatusCode::INTERNAL_SERVER_ERROR).body("".to_string())
+            }, |v| { Response::builder().body(v) })
+        });
+
+    // Handle for receiving the messages, delivering new ones to subscribers and forwarding them
+    // while they have ttl. Duplicates are ignored. Actually, doesn't update state of peers.
     let message_srv = warp::get()
         .and(warp::path("message"))
-        .and(warp::query::<HashMap<String, String>>())
-        .map(move |simple_map: HashMap<String, String>| {
-            let peer_name = match simple_map.get("peer_name") {
-                Some(val) => val,
-                None => {
-                    warn!("No parameter `peer_name`");
-                    return StatusCode::BAD_REQUEST;
+        .and(warp::body::json::<Message>())
+        .map(move |message: Message| {
+            trace!("Received message `{}` from `{}` ", String::from_utf8_lossy(&message.payload), message.origin);
+            match is_authentic(&message, &cache_clone_msg) {
+                Ok(true) => {},
+                Ok(false) => {
+                    warn!("Message `{}` isn't signed by its origin `{}`", message.id, message.origin);
+                    return StatusCode::FORBIDDEN;
+                },
+                Err(err) => {
+                    error!("Error on verifying the message: {}", err);
+                    return StatusCode::INTERNAL_SERVER_ERROR;
                 }
             };
-            let msg = match simple_map.get("msg") {
-                Some(val) => val,
-                None => {
-                    warn!("No parameter `msg`");
-                    return StatusCode::BAD_REQUEST;
+            let delivered = inbox_clone.deliver(&message);
+            if let Ok(new) = delivered {
+                stats_msg.message_received(!new);
+            }
+            match delivered {
+                Ok(true) => {
+                    if message.ttl > 0 {
+                        forward(&client_msg, &message, &cache_clone_msg, fanout, &stats_msg).map_err(|err| {
+                            error!("Error on forwarding the message: {}", err);
+                        }).unwrap_or(());
+                    }
+                    StatusCode::OK
+                },
+                Ok(false) => {
+                    trace!("Skipping duplicate message `{}`", message.id);
+                    StatusCode::OK
+                },
+                Err(err) => {
+                    error!("Error on delivering the message: {}", err);
+                    StatusCode::INTERNAL_SERVER_ERROR
                 }
-            };
-            info!("Received message `{}` from `{}` ", msg, peer_name);
-            StatusCode::OK
+            }
+        });
+
+    // Direct probe, which confirms that the node is alive. If `id` is passed, replies with OK
+    // only if it's the id of current node, so the peer, which has been replaced by another one
+    // on the same address, isn't considered alive.
+    let ping_srv = warp::get()
+        .and(warp::path("ping"))
+        .and(warp::query::<HashMap<String, String>>())
+        .map(move |simple_map: HashMap<String, String>| {
+            match (simple_map.get("id"), cache_clone_ping.self_id()) {
+                (Some(id), Ok(self_id)) if self_id.as_ref() != Some(id) => StatusCode::NOT_FOUND,
+                (_, Err(err)) => {
+                    error!("Error on getting own id: {}", err);
+                    StatusCode::INTERNAL_SERVER_ERROR
+                },
+                _ => StatusCode::OK
+            }
+        });
+
+    // Indirect probe: pings the peer with the given `id` on behalf of the `peer_name` and replies with OK
+    // only if it has answered. The address is taken from the own list, so only known peers are probed.
+    let ping_req_srv = warp::get()
+        .and(warp::path("ping-req"))
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(move |simple_map: HashMap<String, String>| {
+            let client = client_probe.clone();
+            let cache = cache_clone_probe.clone();
+            async move {
+                let id = match simple_map.get("id") {
+                    Some(val) => val,
+                    None => {
+                        warn!("No parameter `id`");
+                        return Ok::<StatusCode, warp::Rejection>(StatusCode::BAD_REQUEST);
+                    }
+                };
+                let target = match cache.address_of(id) {
+                    Ok(Some(val)) => val,
+                    Ok(None) => {
+                        info!("Indirect probe of unknown `{}` requested by `{:?}`", id, simple_map.get("peer_name"));
+                        return Ok(StatusCode::NOT_FOUND);
+                    },
+                    Err(err) => {
+                        error!("Error on getting the address of `{}`: {}", id, err);
+                        return Ok(StatusCode::INTERNAL_SERVER_ERROR);
+                    }
+                };
+                trace!("Indirect probe of `{}` at `{}` requested by `{:?}`", id, target, simple_map.get("peer_name"));
+                Ok(probe(&client, &target, id).await)
+            }
         });
 
     let any_srv = warp::any().map(|| {
@@ -83,15 +337,54 @@ pub async fn run_server(bind: &str, port: u16, cert: &str, key: &str, cache: &Pe
 
     let routes = warp::get().and(
         peers_srv
+            .or(query_srv)
             .or(update_peers_srv)
             .or(message_srv)
+            .or(ping_srv)
+            .or(ping_req_srv)
+            .or(digest_srv)
+            .or(sync_srv)
             .or(any_srv),
     );
 
-    warp::serve(routes)
-        .tls()
-        .cert_path(cert)
-        .key_path(key)
-        .run(format!("{}:{}", bind, port).parse::<SocketAddr>().unwrap())
-        .await;
+    let acceptor = TlsAcceptor::from(Arc::new(build_tls(tls)?));
+    let addr = format!("{}:{}", config.bind, config.port).parse::<SocketAddr>()
+        .map_err(|err| format!("Invalid address `{}:{}`: {}", config.bind, config.port, err))?;
+    let listener = TcpListener::bind(addr).await.map_err(|err| format!("Error on binding `{}`: {:?}", addr, err))?;
+    info!("listening on https://{}", addr);
+    let service = warp::service(routes);
+    loop {
+        let (stream, remote) = match listener.accept().await {
+            Ok(val) => val,
+            Err(err) => {
+                error!("Error on accepting the connection: {:?}", err);
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let service = service.clone();
+        tokio::spawn(async move {
+            let stream = match acceptor.accept(stream).await {
+                Ok(val) => val,
+                Err(err) => {
+                    info!("TLS handshake with `{}` has failed: {:?}", remote, err);
+                    return;
+                }
+            };
+            // identity and address of the client are passed to the filters as extensions of every request
+            let identity = stream.get_ref().1.peer_certificates()
+                .and_then(|certs| certs.first())
+                .and_then(|cert| PeerIdentity::from_der(cert).map_err(|err| warn!("{}", err)).ok());
+            let handler = service_fn(move |mut request| {
+                if let Some(identity) = &identity {
+                    request.extensions_mut().insert(identity.clone());
+                }
+                request.extensions_mut().insert(remote);
+                service.clone().call(request)
+            });
+            if let Err(err) = Http::new().serve_connection(stream, handler).await {
+                info!("Error on serving `{}`: {:?}", remote, err);
+            }
+        });
+    }
 }
\ No newline at end of file
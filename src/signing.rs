@@ -0,0 +1,62 @@
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use log::info;
+
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+
+/// Ed25519 identity of the node. Public key and signatures are hex encoded,
+/// so they can be sent inside JSON as strings.
+#[derive(Debug, Clone)]
+pub struct NodeKey {
+    key: SigningKey
+}
+
+impl NodeKey {
+    pub fn generate() -> Self {
+        NodeKey { key: SigningKey::generate(&mut rand::rngs::OsRng) }
+    }
+
+    /// Loads the hex encoded secret key from the file or generates a new one and saves it there.
+    /// New file is readable only by the owner and is never written over the existing one.
+    pub fn load_or_generate(path: &str) -> Result<Self, String> {
+        if !Path::new(path).exists() {
+            let key = NodeKey::generate();
+            fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(path)
+                .and_then(|mut file| file.write_all(hex::encode(key.key.to_bytes()).as_bytes()))
+                .map_err(|err| format!("Error on writing the node key `{}`: {:?}", path, err))?;
+            info!("Generated new node key `{}`", path);
+            return Ok(key);
+        }
+        let data = fs::read_to_string(path).map_err(|err| format!("Error on reading the node key `{}`: {:?}", path, err))?;
+        let secret: [u8; 32] = hex::decode(data.trim()).ok()
+            .and_then(|val| val.try_into().ok())
+            .ok_or_else(|| format!("Node key `{}` isn't a hex encoded 32 bytes key", path))?;
+        Ok(NodeKey { key: SigningKey::from_bytes(&secret) })
+    }
+
+    /// Returns the hex encoded public key.
+    pub fn public(&self) -> String {
+        hex::encode(self.key.verifying_key().to_bytes())
+    }
+
+    /// Returns the hex encoded signature of the data.
+    pub fn sign(&self, data: &[u8]) -> String {
+        hex::encode(self.key.sign(data).to_bytes())
+    }
+}
+
+/// Returns true if the hex encoded `signature` of the data is made by the owner of the `public` key.
+pub fn verify(public: &str, data: &[u8], signature: &str) -> bool {
+    let public: Option<[u8; 32]> = hex::decode(public).ok().and_then(|val| val.try_into().ok());
+    let signature: Option<[u8; 64]> = hex::decode(signature).ok().and_then(|val| val.try_into().ok());
+    match (public.and_then(|val| VerifyingKey::from_bytes(&val).ok()), signature) {
+        (Some(public), Some(signature)) => public.verify(data, &Signature::from_bytes(&signature)).is_ok(),
+        _ => false
+    }
+}
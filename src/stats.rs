@@ -0,0 +1,90 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Counters of the gossip activity of the node, shared by the services and the server.
+#[derive(Debug, Default)]
+pub struct Stats {
+    messages_sent: AtomicU64,
+    messages_received: AtomicU64,
+    messages_duplicate: AtomicU64,
+    messages_forwarded: AtomicU64,
+    probes: AtomicU64,
+    probes_failed: AtomicU64,
+    updates_sent: AtomicU64,
+    updates_received: AtomicU64,
+    syncs: AtomicU64,
+}
+
+/// Values of the counters at some moment.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct StatsSnapshot {
+    /// Messages of the node sent to peers, one per peer
+    pub messages_sent: u64,
+    /// New messages received from peers
+    pub messages_received: u64,
+    /// Received messages, which have been seen already
+    pub messages_duplicate: u64,
+    /// Messages of other peers forwarded further, one per peer
+    pub messages_forwarded: u64,
+    /// Peers pinged
+    pub probes: u64,
+    /// Peers, which couldn't be reached neither directly nor indirectly
+    pub probes_failed: u64,
+    /// Updates of the PeerList delivered to peers
+    pub updates_sent: u64,
+    /// Updates of the PeerList received from peers
+    pub updates_received: u64,
+    /// Completed anti-entropy rounds
+    pub syncs: u64,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Stats::default()
+    }
+
+    pub fn messages_sent(&self, count: u64) {
+        self.messages_sent.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn message_received(&self, duplicate: bool) {
+        let counter = if duplicate { &self.messages_duplicate } else { &self.messages_received };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn messages_forwarded(&self, count: u64) {
+        self.messages_forwarded.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn probed(&self, reached: bool) {
+        self.probes.fetch_add(1, Ordering::Relaxed);
+        if !reached {
+            self.probes_failed.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn updates_sent(&self, count: u64) {
+        self.updates_sent.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn update_received(&self) {
+        self.updates_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn synced(&self) {
+        self.syncs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            messages_received: self.messages_received.load(Ordering::Relaxed),
+            messages_duplicate: self.messages_duplicate.load(Ordering::Relaxed),
+            messages_forwarded: self.messages_forwarded.load(Ordering::Relaxed),
+            probes: self.probes.load(Ordering::Relaxed),
+            probes_failed: self.probes_failed.load(Ordering::Relaxed),
+            updates_sent: self.updates_sent.load(Ordering::Relaxed),
+            updates_received: self.updates_received.load(Ordering::Relaxed),
+            syncs: self.syncs.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -1,25 +1,54 @@
 
 use std::sync::{Condvar, Mutex, Arc};
 use log::error;
+use tokio::sync::watch;
 
 /// Very simple waiter/signaller, which is mostly copied from Condvar docs.
+/// Can be waited both from threads and from async tasks through subscriptions.
 #[derive(Debug, Clone)]
 pub struct Waiter {
     mtx: Arc<Mutex<bool>>,
     cv: Arc<Condvar>,
+    // number of broadcasts, which every subscription watches
+    broadcasts: Arc<watch::Sender<u64>>,
+}
+
+/// Subscription of the async task to the broadcasts of the Waiter. Every subscription sees
+/// every broadcast made after it has been created, independently of the other ones.
+#[derive(Debug, Clone)]
+pub struct Subscription {
+    broadcasts: watch::Receiver<u64>,
+}
+
+impl Subscription {
+    /// Suspends current task until the next broadcast. Returns at once if there has been
+    /// a broadcast since the previous wait, several of them are seen as one.
+    pub async fn wait(&mut self) {
+        if self.broadcasts.changed().await.is_err() {
+            // all Waiters are dropped, so there will be no broadcasts anymore
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+impl Default for Waiter {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Waiter {
     pub fn new() -> Self {
         Waiter {
             mtx: Arc::new(Mutex::new(false)),
-            cv: Arc::new(Condvar::new())}
+            cv: Arc::new(Condvar::new()),
+            broadcasts: Arc::new(watch::channel(0).0)}
     }
 
     /// Suspends execution of current thread and waits for *broadcast()* to be triggered.
-    pub fn wait(&self) -> Result<(), ()> {
+    pub fn wait(&self) -> Result<(), String> {
         let mut started = self.mtx.lock()
-            .map_err(|err| {error!("Poisoned mutex: {:?}", err);()})?;
+            .map_err(|err| {error!("Poisoned mutex: {:?}", err); format!("Poisoned mutex: {:?}", err)})?;
         while !*started {
             started = self.cv.wait(started).unwrap();
         }
@@ -27,12 +56,18 @@ impl Waiter {
         Ok(())
     }
 
-    /// Resumes waiting threads.
-    pub fn broadcast(&self) -> Result<(), ()> {
+    /// Subscribes the async task to the broadcasts made from now on.
+    pub fn subscribe(&self) -> Subscription {
+        Subscription { broadcasts: self.broadcasts.subscribe() }
+    }
+
+    /// Resumes waiting threads and all subscriptions.
+    pub fn broadcast(&self) -> Result<(), String> {
         let mut started = self.mtx.lock()
-            .map_err(|err| {error!("Poisoned mutex: {:?}", err);()})?;
+            .map_err(|err| {error!("Poisoned mutex: {:?}", err); format!("Poisoned mutex: {:?}", err)})?;
         *started = true;
         self.cv.notify_all();
+        self.broadcasts.send_modify(|count| *count = count.wrapping_add(1));
         Ok(())
     }
 }
\ No newline at end of file
@@ -1,3 +1,3 @@
 #!/bin/bash
 
-cargo test --features mock_time
\ No newline at end of file
+cargo test
\ No newline at end of file
@@ -0,0 +1,65 @@
+#[cfg(test)]
+mod test {
+    use simplep2pgossip::admin::admin_routes;
+    use simplep2pgossip::node::Node;
+    use simplep2pgossip::p2pcache::{PeerCache, PeerList, PeerState};
+    use simplep2pgossip::saabisu::SaabisuConfig;
+    use simplep2pgossip::server::query_route;
+    use serde_json::{from_slice as js_from_slice, Value};
+
+    fn cache() -> Result<PeerCache, String> {
+        let mut cache = PeerCache::new(0);
+        assert!(cache.set_self("me", "127.0.0.1:8080")?);
+        let mut db = PeerState::new("db", "127.0.0.1:8081", 0, false);
+        db.metadata.insert("role".to_string(), "db".to_string());
+        assert!(cache.update_from_list(&PeerList { peers: vec![db] })?);
+        Ok(cache)
+    }
+
+    fn ids(body: &[u8]) -> Result<Vec<String>, String> {
+        let list: PeerList = js_from_slice(body).map_err(|err| format!("{:?}", err))?;
+        Ok(list.peers.into_iter().map(|val| val.id).collect())
+    }
+
+    #[tokio::test]
+    async fn test_admin_routes() -> Result<(), String> {
+        let node = Node::new("me", &cache()?, &SaabisuConfig::default())?;
+        let routes = admin_routes(&node);
+
+        let reply = warp::test::request().path("/info").reply(&routes).await;
+        assert_eq!(reply.status(), 200);
+        let info: Value = js_from_slice(reply.body()).map_err(|err| format!("{:?}", err))?;
+        assert_eq!(info["id"], "me");
+        assert_eq!(info["address"], "127.0.0.1:8080");
+        assert_eq!(info["config"]["fanout"], 3);
+
+        let reply = warp::test::request().path("/peers").reply(&routes).await;
+        assert_eq!(reply.status(), 200);
+        assert_eq!(ids(reply.body())?, vec!["db", "me"]);
+        let reply = warp::test::request().path("/peers?available=true").reply(&routes).await;
+        assert_eq!(ids(reply.body())?, vec!["me"]);
+
+        let reply = warp::test::request().path("/stats").reply(&routes).await;
+        assert_eq!(reply.status(), 200);
+        let stats: Value = js_from_slice(reply.body()).map_err(|err| format!("{:?}", err))?;
+        assert_eq!(stats["messages_sent"], 0);
+
+        let reply = warp::test::request().method("POST").path("/info").reply(&routes).await;
+        assert_eq!(reply.status(), 405);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_route() -> Result<(), String> {
+        let routes = query_route(&cache()?);
+        let reply = warp::test::request().path("/peers?tag=role:db").reply(&routes).await;
+        assert_eq!(reply.status(), 200);
+        assert_eq!(ids(reply.body())?, vec!["db"]);
+
+        let reply = warp::test::request().path("/peers?max_age=4294967295").reply(&routes).await;
+        assert_eq!(ids(reply.body())?, vec!["db", "me"]);
+        let reply = warp::test::request().path("/peers?available=maybe").reply(&routes).await;
+        assert_eq!(reply.status(), 400);
+        Ok(())
+    }
+}
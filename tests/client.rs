@@ -0,0 +1,19 @@
+#[cfg(test)]
+mod test {
+    use simplep2pgossip::client::{build_client, TlsConfig, Verification};
+
+    fn tls(verification: Verification) -> TlsConfig {
+        TlsConfig { cert: "tls/peer.pem".to_string(), key: "tls/peer.key".to_string(), verification }
+    }
+
+    #[test]
+    fn test_build_client() {
+        assert!(build_client(&tls(Verification::Insecure)).is_ok());
+        assert!(build_client(&tls(Verification::System)).is_ok());
+        assert!(build_client(&tls(Verification::Ca("tls/ca.pem".to_string()))).is_ok());
+        assert!(build_client(&tls(Verification::Ca("tls/missing.pem".to_string()))).is_err());
+        assert!(build_client(&tls(Verification::Ca("Cargo.toml".to_string()))).is_err());
+        // the key isn't in PKCS#8 format
+        assert!(build_client(&TlsConfig { key: "tls/key.pem".to_string(), ..tls(Verification::Ca("tls/ca.pem".to_string())) }).is_err());
+    }
+}
@@ -0,0 +1,38 @@
+#[cfg(test)]
+mod test {
+    use simplep2pgossip::hlc::Hlc;
+
+    #[test]
+    fn test_tick() {
+        let mut clock = Hlc::default();
+        assert_eq!(clock.tick(10), Hlc::new(10, 0));
+        assert_eq!(clock.tick(10), Hlc::new(10, 1));
+        // physical clock went backwards
+        assert_eq!(clock.tick(5), Hlc::new(10, 2));
+        assert_eq!(clock.tick(11), Hlc::new(11, 0));
+    }
+
+    #[test]
+    fn test_observe() {
+        let mut clock = Hlc::new(10, 3);
+        // remote is ahead
+        assert_eq!(clock.observe(&Hlc::new(20, 5), 15), Hlc::new(20, 6));
+        // remote is at the same wall time
+        assert_eq!(clock.observe(&Hlc::new(20, 8), 15), Hlc::new(20, 9));
+        // remote is behind
+        assert_eq!(clock.observe(&Hlc::new(12, 0), 15), Hlc::new(20, 10));
+        // physical clock is ahead of both
+        assert_eq!(clock.observe(&Hlc::new(21, 0), 30), Hlc::new(30, 0));
+        assert!(Hlc::new(30, 0) > Hlc::new(29, 100));
+    }
+
+    #[test]
+    fn test_saturation() {
+        let mut clock = Hlc::new(10, u32::MAX - 1);
+        assert_eq!(clock.tick(5), Hlc::new(10, u32::MAX));
+        assert_eq!(clock.tick(5), Hlc::new(10, u32::MAX));
+        assert_eq!(clock.observe(&Hlc::new(10, u32::MAX), 5), Hlc::new(10, u32::MAX));
+        assert_eq!(clock.observe(&Hlc::new(20, u32::MAX), 5), Hlc::new(20, u32::MAX));
+        assert_eq!(clock.observe(&Hlc::new(i64::MIN, 0), 30), Hlc::new(30, 0));
+    }
+}
@@ -0,0 +1,37 @@
+#[cfg(test)]
+mod test {
+    use simplep2pgossip::identity::PeerIdentity;
+
+    use std::fs::File;
+    use std::io::BufReader;
+
+    fn load(path: &str) -> Result<PeerIdentity, String> {
+        let file = File::open(path).map_err(|err| format!("{:?}", err))?;
+        let cert = rustls_pemfile::certs(&mut BufReader::new(file))
+            .next()
+            .ok_or("No certificate")?
+            .map_err(|err| format!("{:?}", err))?;
+        PeerIdentity::from_der(&cert)
+    }
+
+    #[test]
+    fn test_alt_names() -> Result<(), String> {
+        let identity = load("tls/peer.pem")?;
+        assert_eq!(identity.names, vec!["127.0.0.1".to_string(), "localhost".to_string()]);
+        assert!(identity.matches("127.0.0.1:8080"));
+        assert!(identity.matches("LocalHost:8081"));
+        assert!(!identity.matches("127.0.0.2:8080"));
+        // common name is ignored, when there are alternative names
+        assert!(!identity.matches("peer:8080"));
+        assert!(PeerIdentity::from_der(b"garbage").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_common_name() -> Result<(), String> {
+        let identity = PeerIdentity { names: vec!["::1".to_string()] };
+        assert!(identity.matches("[0:0:0:0:0:0:0:1]:8080"));
+        assert_eq!(load("tls/cert.pem")?.names, vec!["none".to_string()]);
+        Ok(())
+    }
+}
@@ -0,0 +1,59 @@
+#[cfg(test)]
+mod test {
+    use simplep2pgossip::merkle::{bucket_of, Digest, BUCKETS};
+    use simplep2pgossip::p2pcache::{PeerCache, PeerList, PeerState};
+
+    #[test]
+    fn test_digest() -> Result<(), String> {
+        let mut first = PeerCache::new(0);
+        let mut second = PeerCache::new(0);
+        let peers: PeerList = PeerList { peers: vec![
+            PeerState::new("a", "a", 0, true),
+            PeerState::new("b", "b", 1, false),
+            PeerState::new("c", "c", 2, true),
+        ]};
+        assert!(first.update_from_list(&peers)?);
+        assert!(second.update_from_list(&peers)?);
+        assert_eq!(first.digest()?, second.digest()?);
+        assert_eq!(first.digest()?.diff(&second.digest()?), Vec::<usize>::new());
+        assert_eq!(first.digest()?.buckets.len(), BUCKETS);
+
+        assert!(second.update_from_list(&PeerList { peers: vec![PeerState::new("b", "b", 3, true)] })?);
+        let diff = first.digest()?.diff(&second.digest()?);
+        assert_eq!(diff, vec![bucket_of("b")]);
+        assert_ne!(first.digest()?.root, second.digest()?.root);
+
+        // exchanging the differing buckets brings caches in sync
+        assert!(first.update_from_list(&second.get_buckets(&diff)?)?);
+        assert!(!second.update_from_list(&first.get_buckets(&diff)?)?);
+        assert_eq!(first.digest()?, second.digest()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_digest_ignores_timestamps() -> Result<(), String> {
+        let mut first = PeerCache::new(0);
+        let mut second = PeerCache::new(0);
+        assert!(first.update_from_list(&PeerList { peers: vec![
+            PeerState::new("a", "a", 10, true),
+            PeerState::new("b", "b", 20, false)] })?);
+        assert!(second.update_from_list(&PeerList { peers: vec![
+            PeerState::new("a", "a", 15, true),
+            PeerState::new("b", "b", 25, false)] })?);
+        assert_ne!(first.get_list()?, second.get_list()?);
+        assert_eq!(first.digest()?, second.digest()?);
+
+        assert!(second.update_from_list(&PeerList { peers: vec![PeerState::new("b", "b", 30, true)] })?);
+        assert_eq!(first.digest()?.diff(&second.digest()?), vec![bucket_of("b")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_digest() {
+        let empty = Digest::build([].iter());
+        let single = Digest::build([PeerState::new("a", "a", 0, true)].iter());
+        assert_eq!(empty.diff(&single), vec![bucket_of("a")]);
+        assert_eq!(empty, Digest::build([].iter()));
+    }
+}
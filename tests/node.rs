@@ -0,0 +1,106 @@
+#[cfg(test)]
+mod test {
+    use simplep2pgossip::node::{Inbox, Message, Node, Outbox, SeenCache};
+    use simplep2pgossip::p2pcache::PeerCache;
+    use simplep2pgossip::saabisu::SaabisuConfig;
+    use simplep2pgossip::server::ServerConfig;
+    use simplep2pgossip::signing::NodeKey;
+
+    #[test]
+    fn test_inbox() -> Result<(), String> {
+        let inbox = Inbox::new();
+        let message = Message::new("a", 2, b"hello".to_vec());
+        assert!(inbox.deliver(&message)?);
+        // duplicates are not delivered
+        let first = inbox.subscribe()?;
+        let second = inbox.subscribe()?;
+        assert!(!inbox.deliver(&message)?);
+        assert!(first.try_recv().is_err());
+
+        let message = Message::new("a", 2, b"hello".to_vec());
+        assert!(inbox.deliver(&message)?);
+        assert_eq!(first.try_recv().map_err(|err| format!("{:?}", err))?, message);
+        assert_eq!(second.try_recv().map_err(|err| format!("{:?}", err))?, message);
+        assert!(first.try_recv().is_err());
+
+        // dropped subscribers do not break delivery
+        drop(first);
+        let message = Message::new("b", 2, b"world".to_vec());
+        assert!(inbox.deliver(&message)?);
+        assert_eq!(second.try_recv().map_err(|err| format!("{:?}", err))?, message);
+        Ok(())
+    }
+
+    #[test]
+    fn test_outbox() -> Result<(), String> {
+        let outbox = Outbox::new();
+        let outbox_copy = outbox.clone();
+        let (first, second) = (Message::new("me", 1, b"a".to_vec()), Message::new("me", 1, b"b".to_vec()));
+        outbox.push(first.clone())?;
+        outbox_copy.push(second.clone())?;
+        assert_eq!(outbox.drain()?, vec![first, second]);
+        assert!(outbox_copy.drain()?.is_empty());
+
+        let node = Node::new("me", &PeerCache::new(0), &SaabisuConfig::default())?;
+        assert!(node.broadcast(b"payload".to_vec()).is_ok());
+        assert_eq!(node.id(), "me");
+        Ok(())
+    }
+
+    #[test]
+    fn test_info() -> Result<(), String> {
+        let mut cache = PeerCache::new(0);
+        assert!(cache.set_self("me", "127.0.0.1:8080")?);
+        let node = Node::new("me", &cache, &SaabisuConfig::default())?;
+        let info = node.info()?;
+        assert_eq!((info.id.as_str(), info.version.as_str()), ("me", env!("CARGO_PKG_VERSION")));
+        assert_eq!(info.address, Some("127.0.0.1:8080".to_string()));
+        assert_eq!(info.config.fanout, 3);
+        assert_eq!(node.stats().messages_sent, 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run_server_errors() -> Result<(), String> {
+        let mut missing = SaabisuConfig::default();
+        missing.tls.cert = "missing.pem".to_string();
+        let node = Node::new("me", &PeerCache::new(0), &missing)?;
+        assert!(node.run_server(&ServerConfig { bind: "127.0.0.1".to_string(), port: 0 }).await.is_err());
+
+        let mut valid = SaabisuConfig::default();
+        valid.tls.cert = "tls/peer.pem".to_string();
+        valid.tls.key = "tls/peer.key".to_string();
+        let node = Node::new("me", &PeerCache::new(0), &valid)?;
+        let busy = std::net::TcpListener::bind("127.0.0.1:0").map_err(|err| format!("{:?}", err))?;
+        let port = busy.local_addr().map_err(|err| format!("{:?}", err))?.port();
+        let err = node.run_server(&ServerConfig { bind: "127.0.0.1".to_string(), port }).await.err().ok_or("Server has stopped")?;
+        assert!(err.contains("binding"), "{}", err);
+        Ok(())
+    }
+
+    #[test]
+    fn test_seen_cache() -> Result<(), String> {
+        let seen = SeenCache::new();
+        let seen_copy = seen.clone();
+        assert!(seen.insert("a")?);
+        assert!(!seen_copy.insert("a")?);
+        assert!(seen_copy.insert("b")?);
+        assert_ne!(Message::new("me", 1, vec![]).id, Message::new("me", 1, vec![]).id);
+        Ok(())
+    }
+
+    #[test]
+    fn test_signed_message() {
+        let key = NodeKey::generate();
+        let mut message = Message::new("me", 2, b"payload".to_vec());
+        assert!(!message.is_signed());
+        message.sign(&key);
+        assert!(message.is_signed());
+        assert_eq!(message.key, key.public());
+        // ttl is changed by forwarding peers and isn't signed
+        message.ttl -= 1;
+        assert!(message.is_signed());
+        message.payload = b"forged".to_vec();
+        assert!(!message.is_signed());
+    }
+}
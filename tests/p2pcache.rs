@@ -1,23 +1,28 @@
 #[cfg(test)]
 mod test {
-    use simplep2pgossip::p2pcache::{PeerCache, PeerList, PeerState};
+    use simplep2pgossip::p2pcache::{PeerCache, PeerList, PeerQuery, PeerState};
+    use simplep2pgossip::hlc::Hlc;
+    use simplep2pgossip::clock::{Clock, ManualClock, OffsetClock, SystemClock};
+    use simplep2pgossip::signing::NodeKey;
+    use std::collections::BTreeMap;
+    use std::sync::Arc;
 
     #[test]
     fn test_update_from_list() -> Result<(), String> {
         let mut cache = PeerCache::new(0);
         let initial_peers: PeerList = PeerList { peers: vec![
-            PeerState { address: "a".to_string(), timestamp: 0, available: true},
-            PeerState { address: "b".to_string(), timestamp: 1, available: false},
-            PeerState { address: "c".to_string(), timestamp: 2, available: true},
+            PeerState::new("a", "a", 0, true),
+            PeerState::new("b", "b", 1, false),
+            PeerState::new("c", "c", 2, true),
         ]};
         assert!(cache.update_from_list(&initial_peers)?);
         assert_eq!(cache.get_list()?, initial_peers);
 
         let extended_peers: PeerList = PeerList { peers: vec![
-            PeerState { address: "a".to_string(), timestamp: 0, available: true},
-            PeerState { address: "b".to_string(), timestamp: 2, available: true},
-            PeerState { address: "c".to_string(), timestamp: 3, available: false},
-            PeerState { address: "d".to_string(), timestamp: 4, available: false},
+            PeerState::new("a", "a", 0, true),
+            PeerState::new("b", "b", 2, true),
+            PeerState::new("c", "c", 3, false),
+            PeerState::new("d", "d", 4, false),
         ]};
         assert!(cache.update_from_list(&extended_peers)?);
         assert_eq!(cache.get_list()?, extended_peers);
@@ -27,102 +32,558 @@ mod test {
         assert_eq!(cache.get_list()?, extended_peers);
 
         let outdated_peers: PeerList = PeerList { peers: vec![
-            PeerState { address: "a".to_string(), timestamp: 1, available: true},
-            PeerState { address: "b".to_string(), timestamp: 3, available: false},
-            PeerState { address: "c".to_string(), timestamp: 2, available: true},
-            PeerState { address: "d".to_string(), timestamp: 3, available: false},
+            PeerState::new("a", "a", 1, true),
+            PeerState::new("b", "b", 3, false),
+            PeerState::new("c", "c", 2, true),
+            PeerState::new("d", "d", 3, false),
         ]};
         assert!(cache.update_from_list(&outdated_peers)?);
         assert_eq!(cache.get_list()?, PeerList { peers: vec![
-            PeerState { address: "a".to_string(), timestamp: 1, available: true },
-            PeerState { address: "b".to_string(), timestamp: 3, available: false },
-            PeerState { address: "c".to_string(), timestamp: 3, available: false },
-            PeerState { address: "d".to_string(), timestamp: 4, available: false }] });
+            PeerState::new("a", "a", 1, true),
+            PeerState::new("b", "b", 3, false),
+            PeerState::new("c", "c", 3, false),
+            PeerState::new("d", "d", 4, false)] });
 
         let refreshed_peers: PeerList = PeerList { peers: vec![
-            PeerState { address: "a".to_string(), timestamp: 2, available: true},
-            PeerState { address: "b".to_string(), timestamp: 4, available: false},
-            PeerState { address: "c".to_string(), timestamp: 5, available: true},
-            PeerState { address: "d".to_string(), timestamp: 6, available: false},
+            PeerState::new("a", "a", 2, true),
+            PeerState::new("b", "b", 4, false),
+            PeerState::new("c", "c", 5, true),
+            PeerState::new("d", "d", 6, false),
         ]};
-        assert!(!cache.update_from_list(&refreshed_peers)?);
+        assert!(cache.update_from_list(&refreshed_peers)?); // `c` has become available
         assert_eq!(cache.get_list()?, PeerList { peers: vec![
-            PeerState { address: "a".to_string(), timestamp: 2, available: true },
-            PeerState { address: "b".to_string(), timestamp: 4, available: false },
-            PeerState { address: "c".to_string(), timestamp: 5, available: true },
-            PeerState { address: "d".to_string(), timestamp: 6, available: false }] });
+            PeerState::new("a", "a", 2, true),
+            PeerState::new("b", "b", 4, false),
+            PeerState::new("c", "c", 5, true),
+            PeerState::new("d", "d", 6, false)] });
 
         Ok(())
     }
 
     #[test]
-    #[cfg(feature = "mock_time")]
     fn test_update_peer() -> Result<(), String> {
-        let mut cache = PeerCache::new(0);
-        cache.set_current_time(1);
-        assert!(cache.update_peer("a", true)?);
-        assert!(cache.update_peer("b", true)?);
-        assert!(cache.update_peer("c", false)?);
-        assert!(cache.update_peer("d", false)?);
+        let clock = ManualClock::new(0);
+        let mut cache = PeerCache::with_clock(0, Arc::new(clock.clone()));
+        clock.set(1);
+        assert!(cache.update_peer("a", "a", true)?);
+        assert!(cache.update_peer("b", "b", true)?);
+        assert!(cache.update_peer("c", "c", false)?);
+        assert!(cache.update_peer("d", "d", false)?);
         assert_eq!(cache.get_list()?, PeerList { peers: vec![
-            PeerState { address: "a".to_string(), timestamp: 1, available: true },
-            PeerState { address: "b".to_string(), timestamp: 1, available: true },
-            PeerState { address: "c".to_string(), timestamp: 1, available: false },
-            PeerState { address: "d".to_string(), timestamp: 1, available: false }] });
+            PeerState::new("a", "a", 1, true),
+            PeerState::new("b", "b", Hlc::new(1, 1), true),
+            PeerState::new("c", "c", Hlc::new(1, 2), false),
+            PeerState::new("d", "d", Hlc::new(1, 3), false)] });
 
         // do not update unavailable users
-        cache.set_current_time(2);
-        assert!(!cache.update_peer("a", true)?);
-        assert!(!cache.update_peer("b", true)?);
-        assert!(!cache.update_peer("c", false)?);
-        assert!(!cache.update_peer("d", false)?);
+        clock.set(2);
+        assert!(!cache.update_peer("a", "a", true)?);
+        assert!(!cache.update_peer("b", "b", true)?);
+        assert!(!cache.update_peer("c", "c", false)?);
+        assert!(!cache.update_peer("d", "d", false)?);
         assert_eq!(cache.get_list()?, PeerList { peers: vec![
-            PeerState { address: "a".to_string(), timestamp: 2, available: true },
-            PeerState { address: "b".to_string(), timestamp: 2, available: true },
-            PeerState { address: "c".to_string(), timestamp: 1, available: false },
-            PeerState { address: "d".to_string(), timestamp: 1, available: false }] });
-
-        cache.set_current_time(3);
-        assert!(cache.update_peer("a", false)?);
-        assert!(cache.update_peer("b", false)?);
-        assert!(cache.update_peer("c", true)?);
-        assert!(cache.update_peer("d", true)?);
+            PeerState::new("a", "a", 2, true),
+            PeerState::new("b", "b", Hlc::new(2, 1), true),
+            PeerState::new("c", "c", Hlc::new(1, 2), false),
+            PeerState::new("d", "d", Hlc::new(1, 3), false)] });
+
+        clock.set(3);
+        assert!(cache.update_peer("a", "a", false)?);
+        assert!(cache.update_peer("b", "b", false)?);
+        assert!(cache.update_peer("c", "c", true)?);
+        assert!(cache.update_peer("d", "d", true)?);
         assert_eq!(cache.get_list()?, PeerList { peers: vec![
-            PeerState { address: "a".to_string(), timestamp: 3, available: false },
-            PeerState { address: "b".to_string(), timestamp: 3, available: false },
-            PeerState { address: "c".to_string(), timestamp: 3, available: true },
-            PeerState { address: "d".to_string(), timestamp: 3, available: true }] });
+            PeerState::new("a", "a", 3, false),
+            PeerState::new("b", "b", Hlc::new(3, 1), false),
+            PeerState::new("c", "c", Hlc::new(3, 2), true),
+            PeerState::new("d", "d", Hlc::new(3, 3), true)] });
 
         Ok(())
     }
 
     #[test]
-    #[cfg(feature = "mock_time")]
     fn test_cleanup_old_peers() -> Result<(), String> {
-        let mut cache = PeerCache::new(5);
+        let clock = ManualClock::new(0);
+        let mut cache = PeerCache::with_clock(5, Arc::new(clock.clone()));
         let initial_peers: PeerList = PeerList { peers: vec![
-            PeerState { address: "a".to_string(), timestamp: 0, available: true},
-            PeerState { address: "b".to_string(), timestamp: 1000, available: false},
-            PeerState { address: "c".to_string(), timestamp: 2000, available: true},
-            PeerState { address: "d".to_string(), timestamp: 3000, available: false},
+            PeerState::new("a", "a", 0, true),
+            PeerState::new("b", "b", 1000, false),
+            PeerState::new("c", "c", 2000, true),
+            PeerState::new("d", "d", 3000, false),
         ]};
         assert!(cache.update_from_list(&initial_peers)?);
         assert_eq!(cache.cleanup_old_peers()?, ());
         assert_eq!(cache.get_list()?, initial_peers);
 
-        cache.set_current_time(1001 + 5000);
+        clock.set(1001 + 5000);
         assert_eq!(cache.cleanup_old_peers()?, ());
         assert_eq!(cache.get_list()?, PeerList { peers: vec![
-            PeerState { address: "a".to_string(), timestamp: 0, available: true},
-            PeerState { address: "c".to_string(), timestamp: 2000, available: true},
-            PeerState { address: "d".to_string(), timestamp: 3000, available: false},] });
+            PeerState::new("a", "a", 0, true),
+            PeerState::new("c", "c", 2000, true),
+            PeerState::new("d", "d", 3000, false),] });
 
-        cache.set_current_time(3001 + 5000);
+        clock.set(3001 + 5000);
         assert_eq!(cache.cleanup_old_peers()?, ());
         assert_eq!(cache.get_list()?, PeerList { peers: vec![
-            PeerState { address: "a".to_string(), timestamp: 0, available: true},
-            PeerState { address: "c".to_string(), timestamp: 2000, available: true}] });
+            PeerState::new("a", "a", 0, true),
+            PeerState::new("c", "c", 2000, true)] });
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_suspect_peer() -> Result<(), String> {
+        let clock = ManualClock::new(0);
+        let mut cache = PeerCache::with_clock(30, Arc::new(clock.clone()));
+        clock.set(1000);
+        assert!(cache.update_peer("a", "a", true)?);
+        assert!(cache.update_peer("b", "b", false)?);
+        assert!(!cache.suspect_peer("b")?);
+        assert!(!cache.suspect_peer("c")?);
+
+        clock.set(2000);
+        assert!(cache.suspect_peer("a")?);
+        clock.set(3000);
+        assert!(!cache.suspect_peer("a")?);
+        let mut suspect = PeerState::new("a", "a", 2000, true);
+        suspect.suspect = true;
+        assert_eq!(cache.get_list()?, PeerList { peers: vec![suspect, PeerState::new("b", "b", Hlc::new(1000, 1), false)] });
+
+        // suspicion is refuted by successful probe
+        assert!(cache.update_peer("a", "a", true)?);
+        assert_eq!(cache.get_list()?, PeerList { peers: vec![
+            PeerState::new("a", "a", 3000, true),
+            PeerState::new("b", "b", Hlc::new(1000, 1), false)] });
+
+        assert!(cache.suspect_peer("a")?);
+        clock.set(3000 + 5000);
+        assert!(!cache.expire_suspects(5)?);
+        clock.set(3001 + 5000);
+        assert!(cache.expire_suspects(5)?);
+        assert_eq!(cache.get_list()?, PeerList { peers: vec![
+            PeerState::new("a", "a", 8001, false),
+            PeerState::new("b", "b", Hlc::new(1000, 1), false)] });
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_incarnation() -> Result<(), String> {
+        let mut cache = PeerCache::new(0);
+        assert!(cache.set_self("me", "me")?);
+        let me = cache.get_list()?.peers[0].clone();
+
+        let mut a_old = PeerState::new("a", "a", 5, true);
+        a_old.incarnation = 1;
+        assert!(cache.update_from_list(&PeerList { peers: vec![a_old.clone()] })?);
+        // newer timestamp doesn't win over higher incarnation
+        assert!(!cache.update_from_list(&PeerList { peers: vec![PeerState::new("a", "a", 6, false)] })?);
+        let mut a_new = PeerState::new("a", "a", 1, false);
+        a_new.incarnation = 2;
+        assert!(cache.update_from_list(&PeerList { peers: vec![a_new.clone()] })?);
+
+        // alive state of current node with the same incarnation is ignored
+        assert!(!cache.update_from_list(&PeerList { peers: vec![PeerState::new("me", "me", Hlc::new(me.timestamp.wall + 1, 0), true)] })?);
+        // suspicion is refuted with higher incarnation
+        let mut suspect = PeerState::new("me", "me", Hlc::new(me.timestamp.wall + 1, 0), true);
+        suspect.suspect = true;
+        assert!(cache.update_from_list(&PeerList { peers: vec![suspect] })?);
+        let list = cache.get_list()?;
+        assert_eq!(list.peers[0], a_new);
+        assert!(list.peers[1].available && !list.peers[1].suspect);
+        assert_eq!(list.peers[1].incarnation, 1);
+
+        // refutation wins over stale death reports in other caches
+        let mut other = PeerCache::new(0);
+        let mut dead = PeerState::new("me", "me", Hlc::new(me.timestamp.wall + 2, 0), false);
+        assert!(other.update_from_list(&PeerList { peers: vec![dead.clone()] })?);
+        assert!(other.update_from_list(&list)?);
+        dead.incarnation = 1;
+        assert!(cache.update_from_list(&PeerList { peers: vec![dead] })?);
+        assert_eq!(cache.get_list()?.peers[1].incarnation, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_address_change() -> Result<(), String> {
+        let clock = ManualClock::new(1000);
+        let mut before = PeerCache::with_clock(0, Arc::new(clock.clone()));
+        assert!(before.set_self("me", "10.0.0.1:8080")?);
+        let mut other = PeerCache::with_clock(0, Arc::new(clock.clone()));
+        assert!(other.update_from_list(&before.get_list()?)?);
+
+        // the node is restarted with the same id on the new address
+        clock.advance(10);
+        let mut after = PeerCache::with_clock(0, Arc::new(clock.clone()));
+        assert!(after.set_self("me", "10.0.0.2:8080")?);
+        assert!(other.update_from_list(&after.get_list()?)?);
+        assert_eq!(other.get_list()?.peers.len(), 1);
+        assert_eq!(other.address_of("me")?, Some("10.0.0.2:8080".to_string()));
+        // probes don't change the address of known peers
+        assert!(!other.update_peer("me", "10.0.0.1:8080", true)?);
+        assert_eq!(other.address_of("me")?, Some("10.0.0.2:8080".to_string()));
+
+        // stale entry with the old address is refuted
+        assert!(after.update_from_list(&before.get_list()?)?);
+        let own = after.self_state()?.ok_or("no own entry")?;
+        assert_eq!((own.address.as_str(), own.incarnation), ("10.0.0.2:8080", 1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_adopt_observed() -> Result<(), String> {
+        let mut cache = PeerCache::new(0);
+        assert!(!cache.adopt_observed("10.0.0.5:40000")?);
+        assert!(cache.set_self("me", "0.0.0.0:8080")?);
+        assert!(!cache.adopt_observed("garbage")?);
+        assert!(!cache.adopt_observed("0.0.0.0:40000")?);
+        // observed IP is combined with own port
+        assert!(cache.adopt_observed("10.0.0.5:40000")?);
+        assert_eq!(cache.address_of("me")?, Some("10.0.0.5:8080".to_string()));
+        // configured address isn't replaced
+        assert!(!cache.adopt_observed("10.0.0.6:40000")?);
+        assert_eq!(cache.address_of("me")?, Some("10.0.0.5:8080".to_string()));
+
+        let mut v6 = PeerCache::new(0);
+        assert!(v6.set_self("me", "[::]:8080")?);
+        assert!(v6.adopt_observed("[fd00::5]:40000")?);
+        assert_eq!(v6.address_of("me")?, Some("[fd00::5]:8080".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_clock_drift() -> Result<(), String> {
+        let clock = ManualClock::new(0);
+        let mut cache = PeerCache::with_clock(0, Arc::new(clock.clone()));
+        cache.set_max_drift(60);
+        clock.set(1000);
+        let far_future = Hlc::new(1001 + 60_000, 0);
+        assert!(!cache.update_from_list(&PeerList { peers: vec![PeerState::new("a", "a", far_future, true)] })?);
+        assert_eq!(cache.get_list()?, PeerList { peers: vec![] });
+
+        // own timestamps are always issued after the observed ones
+        let near_future = Hlc::new(1000 + 60_000, 7);
+        assert!(cache.update_from_list(&PeerList { peers: vec![PeerState::new("a", "a", near_future, true)] })?);
+        assert!(cache.update_peer("b", "b", true)?);
+        assert_eq!(cache.get_list()?, PeerList { peers: vec![
+            PeerState::new("a", "a", near_future, true),
+            PeerState::new("b", "b", Hlc::new(61000, 9), true)] });
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_extreme_timestamps() -> Result<(), String> {
+        let clock = ManualClock::new(1000);
+        let mut cache = PeerCache::with_clock(5, Arc::new(clock.clone()));
+        assert!(!cache.update_from_list(&PeerList { peers: vec![
+            PeerState::new("a", "a", Hlc::new(i64::MIN, 0), false),
+            PeerState::new("b", "b", Hlc::new(i64::MAX, 0), true),
+            PeerState::new("c", "c", Hlc::new(1000, u32::MAX), true)] })?);
+        assert_eq!(cache.get_list()?, PeerList { peers: vec![] });
+
+        // the cache keeps working after the rejected entries
+        let far_past = Hlc::new(i64::MIN + 2000, 0);
+        assert!(cache.update_from_list(&PeerList { peers: vec![PeerState::new("d", "d", far_past, false)] })?);
+        clock.advance(5001);
+        cache.cleanup_old_peers()?;
+        assert!(cache.update_peer("e", "e", true)?);
+        assert_eq!(cache.get_list()?, PeerList { peers: vec![PeerState::new("e", "e", 6001, true)] });
+        Ok(())
+    }
+
+    #[test]
+    fn test_tombstones() -> Result<(), String> {
+        let clock = ManualClock::new(1000);
+        let mut cache = PeerCache::with_clock(5, Arc::new(clock.clone()));
+        cache.set_tombstone_ttl(20);
+        let dead = PeerState::new("a", "a", 1000, false);
+        assert!(cache.update_from_list(&PeerList { peers: vec![dead.clone()] })?);
+        clock.advance(5001);
+        cache.cleanup_old_peers()?;
+        assert_eq!(cache.get_list()?, PeerList { peers: vec![] });
+
+        // stale entries from peers, which haven't cleaned up yet, are rejected
+        let stale_alive = PeerState::new("a", "a", 900, true);
+        assert!(!cache.update_from_list(&PeerList { peers: vec![dead.clone(), stale_alive] })?);
+        assert_eq!(cache.get_list()?, PeerList { peers: vec![] });
+
+        // but the peer, which has come back, is accepted
+        let mut refuted = dead.clone();
+        refuted.incarnation = 1;
+        assert!(cache.update_from_list(&PeerList { peers: vec![PeerState::new("a", "a", 1500, true)] })?);
+        assert!(!cache.update_from_list(&PeerList { peers: vec![dead.clone()] })?);
+        clock.advance(5001);
+        assert!(cache.update_peer("a", "a", false)?);
+        clock.advance(5001);
+        cache.cleanup_old_peers()?;
+        assert!(cache.update_from_list(&PeerList { peers: vec![refuted] })?);
+
+        // tombstones expire
+        clock.advance(5001);
+        cache.cleanup_old_peers()?;
+        assert!(!cache.update_from_list(&PeerList { peers: vec![dead.clone()] })?);
+        clock.advance(20001);
+        cache.cleanup_old_peers()?;
+        assert!(cache.update_from_list(&PeerList { peers: vec![dead] })?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_shared_clock() -> Result<(), String> {
+        let clock = ManualClock::new(0);
+        let mut cache = PeerCache::with_clock(5, Arc::new(clock.clone()));
+        let mut cache_copy = cache.clone();
+        clock.set(1000);
+        assert!(cache.update_peer("a", "a", false)?);
+        clock.advance(5001);
+        cache_copy.cleanup_old_peers()?;
+        assert_eq!(cache.get_list()?, PeerList { peers: vec![] });
+
+        let offset = OffsetClock::new(Arc::new(clock.clone()), -500);
+        assert_eq!(offset.now(), 5501);
+        assert!(SystemClock.now() > 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_delta() -> Result<(), String> {
+        let clock = ManualClock::new(1000);
+        let mut cache = PeerCache::with_clock(5, Arc::new(clock.clone()));
+        assert_eq!(cache.get_delta(0)?, (PeerList { peers: vec![] }, 0));
+
+        assert!(cache.update_peer("a", "a", true)?);
+        assert!(cache.update_peer("b", "b", true)?);
+        let (full, version) = cache.get_delta(0)?;
+        assert_eq!(full, cache.get_list()?);
+        assert_eq!(cache.get_delta(version)?, (PeerList { peers: vec![] }, version));
+
+        // refreshed timestamps don't get into deltas
+        clock.advance(1);
+        assert!(!cache.update_peer("a", "a", true)?);
+        assert!(!cache.update_from_list(&PeerList { peers: vec![PeerState::new("b", "b", 5000, true)] })?);
+        assert_eq!(cache.get_delta(version)?, (PeerList { peers: vec![] }, version));
+
+        assert!(cache.suspect_peer("b")?);
+        assert!(cache.update_from_list(&PeerList { peers: vec![PeerState::new("c", "c", 1000, false)] })?);
+        let (delta, new_version) = cache.get_delta(version)?;
+        assert_eq!(delta.peers.iter().map(|x| x.address.as_str()).collect::<Vec<&str>>(), vec!["b", "c"]);
+        assert_eq!(new_version, version + 2);
+
+        // removed peers don't appear in deltas
+        clock.advance(5001);
+        cache.cleanup_old_peers()?;
+        let (delta, _) = cache.get_delta(version)?;
+        assert_eq!(delta.peers.iter().map(|x| x.address.as_str()).collect::<Vec<&str>>(), vec!["b"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot() -> Result<(), String> {
+        let path = std::env::temp_dir().join(format!("simplep2pgossip-test-{}.json", std::process::id()));
+        let path = path.to_str().ok_or("Bad temp path")?;
+        let mut cache = PeerCache::new(0);
+        assert!(cache.set_self("me", "me")?);
+        cache.set_snapshot(path)?;
+        assert!(cache.update_peer("a", "a", true)?);
+        assert!(cache.update_peer("b", "b", false)?);
+        let mut suspect = PeerState::new("me", "me", 0, true);
+        suspect.suspect = true;
+        assert!(cache.update_from_list(&PeerList { peers: vec![suspect] })?);
+
+        let mut restored = PeerCache::new(0);
+        assert!(restored.set_self("me", "me")?);
+        assert!(restored.load_snapshot(path)?);
+        let (original, restored_list) = (cache.get_list()?, restored.get_list()?);
+        assert_eq!(original.peers[..2], restored_list.peers[..2]);
+        // own incarnation continues from the saved one
+        assert!(restored_list.peers[2].incarnation > original.peers[2].incarnation);
+
+        std::fs::remove_file(path).map_err(|err| format!("{:?}", err))?;
+        assert!(!PeerCache::new(0).load_snapshot(path)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_choose_available() -> Result<(), String> {
+        let mut cache = PeerCache::new(0);
+        assert!(cache.set_self("me", "me")?);
+        assert_eq!(cache.self_id()?, Some("me".to_string()));
+        for peer in ["a", "b", "c", "d"] {
+            assert!(cache.update_peer(peer, peer, peer != "d")?);
+        }
+        assert_eq!(cache.choose_available(10, &[])?, vec!["a", "b", "c"]);
+        assert_eq!(cache.choose_available(10, &["b"])?, vec!["a", "c"]);
+        let chosen = cache.choose_available(2, &[])?;
+        assert_eq!(chosen.len(), 2);
+        assert!(chosen.iter().all(|peer| ["a", "b", "c"].contains(&peer.as_str())));
+        assert!(cache.choose_available(0, &[])?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_signatures() -> Result<(), String> {
+        let mut remote = PeerCache::new(0);
+        remote.set_key(NodeKey::generate());
+        assert!(remote.set_self("a", "a")?);
+        let signed = remote.get_list()?.peers[0].clone();
+        assert!(signed.is_signed());
+
+        let mut cache = PeerCache::new(0);
+        cache.set_key(NodeKey::generate());
+        assert!(cache.set_self("me", "me")?);
+        // unsigned entry from a direct contact is replaced by the signed one
+        assert!(cache.update_peer("a", "a", true)?);
+        assert!(cache.update_from_list(&PeerList { peers: vec![signed.clone()] })?);
+        assert_eq!(cache.key_of("a")?, Some(signed.key.clone()));
+        assert!(cache.update_peer("a", "a", true).is_ok());
+        assert!(cache.get_list()?.peers[0].is_signed());
+
+        let mut forged = signed.clone();
+        forged.incarnation += 1;
+        let mut resigned = signed.clone();
+        resigned.incarnation += 1;
+        resigned.sign(&NodeKey::generate());
+        let mut unsigned = PeerState::new("b", "b", 0, true);
+        unsigned.incarnation = 1;
+        assert!(!cache.update_from_list(&PeerList { peers: vec![forged, resigned, unsigned] })?);
+        assert_eq!(cache.get_list()?.peers.iter().map(|val| val.incarnation).collect::<Vec<u64>>(), vec![0, 0]);
+
+        // without the node key entries aren't verified
+        let mut plain = PeerCache::new(0);
+        assert!(plain.update_from_list(&PeerList { peers: vec![PeerState::new("b", "b", 0, true)] })?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_leave() -> Result<(), String> {
+        let key = NodeKey::generate();
+        let mut leaving = PeerCache::new(0);
+        leaving.set_key(key.clone());
+        assert!(leaving.set_self("me", "me")?);
+        let mut other = PeerCache::new(0);
+        other.set_key(NodeKey::generate());
+        assert!(other.set_self("a", "a")?);
+        assert!(other.update_from_list(&leaving.get_list()?)?);
+
+        let left = leaving.leave()?.ok_or("no own entry")?;
+        assert!(left.left && !left.available && left.is_signed());
+        assert_eq!(left.incarnation, 1);
+        assert!(other.update_from_list(&PeerList { peers: vec![left.clone()] })?);
+        assert_eq!(other.choose_available(10, &["a"])?, Vec::<String>::new());
+        // left peer isn't brought back by probes
+        assert!(!other.update_peer("me", "me", true)?);
+        let echo: Vec<PeerState> = other.get_list()?.peers.into_iter().filter(|val| val.id == "me").collect();
+        assert!(echo[0].left && !echo[0].available);
+        // leaving node doesn't refute its own leaving
+        assert!(!leaving.update_from_list(&PeerList { peers: echo })?);
+
+        // but it does after restart with the same id
+        let mut restarted = PeerCache::new(0);
+        restarted.set_key(key);
+        assert!(restarted.set_self("me", "me")?);
+        assert!(restarted.update_from_list(&PeerList { peers: vec![left] })?);
+        let own = restarted.self_state()?.ok_or("no own entry")?;
+        assert!(!own.left && own.available);
+        assert_eq!(own.incarnation, 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejoin_after_leave() -> Result<(), String> {
+        let key = NodeKey::generate();
+        let clock = ManualClock::new(1000);
+        let mut leaving = PeerCache::with_clock(5, Arc::new(clock.clone()));
+        leaving.set_key(key.clone());
+        assert!(leaving.set_self("me", "me")?);
+        let mut seed = PeerCache::with_clock(5, Arc::new(clock.clone()));
+        seed.set_key(NodeKey::generate());
+        assert!(seed.set_self("a", "a")?);
+        let before_leaving = leaving.get_list()?;
+        assert!(seed.update_from_list(&before_leaving)?);
+        let left = leaving.leave()?.ok_or("no own entry")?;
+        assert!(seed.update_from_list(&PeerList { peers: vec![left] })?);
+        clock.advance(5001);
+        seed.cleanup_old_peers()?;
+        assert_eq!(seed.address_of("me")?, None);
+        // stale alive entry of the left node is still rejected
+        assert!(!seed.update_from_list(&before_leaving)?);
+
+        // node restarted with the same id and key begins with incarnation 0 and is accepted anyway
+        clock.advance(1000);
+        let mut restarted = PeerCache::with_clock(5, Arc::new(clock.clone()));
+        restarted.set_key(key);
+        assert!(restarted.set_self("me", "me")?);
+        assert!(seed.update_from_list(&restarted.get_list()?)?);
+        assert_eq!(seed.address_of("me")?, Some("me".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_metadata() -> Result<(), String> {
+        let mut node = PeerCache::new(0);
+        node.set_key(NodeKey::generate());
+        assert!(node.set_self("me", "me")?);
+        let tags: BTreeMap<String, String> = [("role", "db"), ("zone", "eu-1")].iter()
+            .map(|(key, value)| (key.to_string(), value.to_string())).collect();
+        assert!(node.set_metadata(tags.clone())?);
+        assert!(!node.set_metadata(tags.clone())?);
+        let own = node.self_state()?.ok_or("no own entry")?;
+        assert!(own.is_signed());
+        assert_eq!(own.incarnation, 1);
+
+        let mut other = PeerCache::new(0);
+        other.set_key(NodeKey::generate());
+        assert!(other.set_self("a", "a")?);
+        assert!(other.update_from_list(&PeerList { peers: vec![own.clone()] })?);
+        assert_eq!(other.metadata_of("me")?, Some(tags.clone()));
+        assert_eq!(other.metadata_of("b")?, None);
+        // probes keep the metadata
+        assert!(!other.update_peer("me", "me", true)?);
+        assert_eq!(other.metadata_of("me")?, Some(tags));
+
+        // newer metadata wins over the old one observed later
+        let stale = other.get_list()?.peers.into_iter().find(|val| val.id == "me").ok_or("no entry")?;
+        assert!(node.set_metadata(BTreeMap::new())?);
+        let mut third = PeerCache::new(0);
+        assert!(third.update_from_list(&node.get_list()?)?);
+        assert!(!third.update_from_list(&PeerList { peers: vec![stale.clone()] })?);
+        assert_eq!(third.metadata_of("me")?, Some(BTreeMap::new()));
+
+        let mut forged = stale;
+        forged.metadata.insert("role".to_string(), "admin".to_string());
+        assert!(!forged.is_signed());
+        Ok(())
+    }
+
+    #[test]
+    fn test_query() -> Result<(), String> {
+        let clock = ManualClock::new(1000);
+        let mut cache = PeerCache::with_clock(0, Arc::new(clock.clone()));
+        let mut db = PeerState::new("a", "a", 1000, true);
+        db.metadata.insert("role".to_string(), "db".to_string());
+        let mut old_db = PeerState::new("b", "b", 500, false);
+        old_db.metadata.insert("role".to_string(), "db".to_string());
+        let mut web = PeerState::new("c", "c", 2000, true);
+        web.metadata.insert("role".to_string(), "web".to_string());
+        let plain = PeerState::new("d", "d", 2000, true);
+        assert!(cache.update_from_list(&PeerList { peers: vec![db, old_db, web, plain] })?);
+        clock.set(3000);
 
+        let ids = |query: PeerQuery| -> Result<Vec<String>, String> {
+            Ok(cache.query(&query)?.peers.into_iter().map(|val| val.id).collect())
+        };
+        assert_eq!(ids(PeerQuery::default())?, vec!["a", "b", "c", "d"]);
+        assert_eq!(ids(PeerQuery { tag: Some("role:db".to_string()), ..Default::default() })?, vec!["a", "b"]);
+        assert_eq!(ids(PeerQuery { tag: Some("role".to_string()), ..Default::default() })?, vec!["a", "b", "c"]);
+        assert_eq!(ids(PeerQuery { tag: Some("role:db".to_string()), available: Some(true), ..Default::default() })?, vec!["a"]);
+        assert_eq!(ids(PeerQuery { available: Some(false), ..Default::default() })?, vec!["b"]);
+        assert_eq!(ids(PeerQuery { max_age: Some(1), ..Default::default() })?, vec!["c", "d"]);
+        assert_eq!(ids(PeerQuery { max_age: Some(2), ..Default::default() })?, vec!["a", "c", "d"]);
+        assert_eq!(ids(PeerQuery { max_age: Some(u32::MAX), ..Default::default() })?, vec!["a", "b", "c", "d"]);
         Ok(())
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,25 @@
+#[cfg(test)]
+mod test {
+    use simplep2pgossip::resolver::{resolve_all, SeedResolver, StaticResolver, SystemResolver};
+
+    #[test]
+    fn test_resolve_all() {
+        let resolver = StaticResolver::new();
+        resolver.set("seeds.cluster:8080", &["10.0.0.1:8080", "10.0.0.2:8080"]);
+        resolver.set("other.cluster:8080", &["10.0.0.2:8080", "10.0.0.3:8080"]);
+        let seeds = vec!["seeds.cluster:8080".to_string(), "unknown:8080".to_string(), "other.cluster:8080".to_string()];
+        assert_eq!(resolve_all(&resolver, &seeds), vec!["10.0.0.1:8080", "10.0.0.2:8080", "10.0.0.3:8080"]);
+
+        // records are re-read on every resolution
+        resolver.clone().set("seeds.cluster:8080", &["10.0.0.4:8080"]);
+        assert_eq!(resolve_all(&resolver, &seeds[..1]), vec!["10.0.0.4:8080"]);
+    }
+
+    #[test]
+    fn test_system_resolver() -> Result<(), String> {
+        assert_eq!(SystemResolver.resolve("127.0.0.1:8080")?, vec!["127.0.0.1:8080"]);
+        assert_eq!(SystemResolver.resolve("[::1]:8080")?, vec!["[::1]:8080"]);
+        assert!(SystemResolver.resolve("no-port").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,39 @@
+#[cfg(test)]
+mod test {
+    use simplep2pgossip::selector::PeerSelector;
+
+    use std::collections::HashSet;
+
+    fn peers(count: usize) -> Vec<String> {
+        (0..count).map(|idx| format!("127.0.0.1:{}", 8080 + idx)).collect()
+    }
+
+    #[test]
+    fn test_fanout() {
+        let mut selector = PeerSelector::new(3, false);
+        let candidates = peers(10);
+        let chosen = selector.choose(&candidates);
+        assert_eq!(chosen.len(), 3);
+        assert!(chosen.iter().all(|peer| candidates.contains(peer)));
+        assert_eq!(chosen.iter().collect::<HashSet<_>>().len(), 3);
+        assert_eq!(selector.choose(&peers(2)).len(), 2);
+        assert!(selector.choose(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_prefer_stale() {
+        let mut selector = PeerSelector::new(2, true);
+        let candidates = peers(5);
+        let mut contacted = HashSet::new();
+        for _ in 0..3 {
+            contacted.extend(selector.choose(&candidates));
+        }
+        // every peer is contacted within peers / fanout rounds
+        assert_eq!(contacted.len(), 5);
+
+        let mut selector = PeerSelector::new(2, true);
+        let first = selector.choose(&candidates);
+        let second = selector.choose(&candidates);
+        assert!(first.iter().all(|peer| !second.contains(peer)));
+    }
+}
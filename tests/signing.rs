@@ -0,0 +1,30 @@
+#[cfg(test)]
+mod test {
+    use simplep2pgossip::signing::{verify, NodeKey};
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_sign() {
+        let key = NodeKey::generate();
+        let signature = key.sign(b"data");
+        assert!(verify(&key.public(), b"data", &signature));
+        assert!(!verify(&key.public(), b"other", &signature));
+        assert!(!verify(&NodeKey::generate().public(), b"data", &signature));
+        assert!(!verify("garbage", b"data", &signature));
+        assert!(!verify(&key.public(), b"data", ""));
+    }
+
+    #[test]
+    fn test_load_or_generate() -> Result<(), String> {
+        let path = std::env::temp_dir().join(format!("simplep2pgossip-key-{}", std::process::id()));
+        let path = path.to_str().ok_or("Bad temp path")?;
+        let generated = NodeKey::load_or_generate(path)?;
+        let mode = std::fs::metadata(path).map_err(|err| format!("{:?}", err))?.permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(NodeKey::load_or_generate(path)?.public(), generated.public());
+        std::fs::write(path, "garbage").map_err(|err| format!("{:?}", err))?;
+        assert!(NodeKey::load_or_generate(path).is_err());
+        std::fs::remove_file(path).map_err(|err| format!("{:?}", err))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,21 @@
+#[cfg(test)]
+mod test {
+    use simplep2pgossip::stats::{Stats, StatsSnapshot};
+
+    #[test]
+    fn test_stats() {
+        let stats = Stats::new();
+        assert_eq!(stats.snapshot(), StatsSnapshot::default());
+        stats.messages_sent(3);
+        stats.message_received(false);
+        stats.message_received(true);
+        stats.messages_forwarded(2);
+        stats.probed(true);
+        stats.probed(false);
+        stats.updates_sent(1);
+        stats.update_received();
+        stats.synced();
+        assert_eq!(stats.snapshot(), StatsSnapshot { messages_sent: 3, messages_received: 1, messages_duplicate: 1,
+            messages_forwarded: 2, probes: 2, probes_failed: 1, updates_sent: 1, updates_received: 1, syncs: 1 });
+    }
+}
@@ -12,7 +12,7 @@ mod test {
         let checker_copy = checker.clone();
         let handle = thread::spawn(move || {
             assert!(wt_copy.wait().is_ok());
-            assert!(checker_copy.lock().and_then(move |mut val_storage| { *val_storage = true; Ok(()) }).is_ok())
+            assert!(checker_copy.lock().map(move |mut val_storage| { *val_storage = true; }).is_ok())
         });
         assert!(wt.broadcast().is_ok());
         assert!(handle.join().is_ok());
@@ -31,10 +31,30 @@ mod test {
         assert!(wt.broadcast().is_ok());
         let handle = thread::spawn(move || {
             assert!(wt_copy.wait().is_ok());
-            assert!(checker_copy.lock().and_then(move |mut val_storage| { *val_storage = true; Ok(()) }).is_ok())
+            assert!(checker_copy.lock().map(move |mut val_storage| { *val_storage = true; }).is_ok())
         });
         assert!(handle.join().is_ok());
         assert!(*checker.lock().map_err(|err| {format!("Error on lock: {:?}", err)})?);
         Ok(())
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_subscribe() -> Result<(), String> {
+        let wt = Waiter::new();
+        let mut first = wt.subscribe();
+        let mut second = wt.clone().subscribe();
+        let handle = tokio::spawn(async move {
+            first.wait().await;
+        });
+        assert!(wt.broadcast().is_ok());
+        handle.await.map_err(|err| format!("Error on join: {:?}", err))?;
+        // every subscription sees the broadcast, even if it wasn't waiting at that moment
+        second.wait().await;
+
+        assert!(wt.broadcast().is_ok());
+        assert!(wt.broadcast().is_ok());
+        second.wait().await;
+        assert!(tokio::time::timeout(std::time::Duration::from_millis(50), second.wait()).await.is_err());
+        Ok(())
+    }
+}